@@ -0,0 +1,57 @@
+//! Compares formatting through a full `String` round trip, as done before formatting worked on
+//! the first line only, with `format_shebang_bytes`.
+//!
+//! Run with `cargo bench`.
+
+use dprint_plugin_shebang::Configuration;
+use dprint_plugin_shebang::format_shebang;
+use dprint_plugin_shebang::format_shebang_bytes;
+use std::hint::black_box;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+const FILES: usize = 10_000;
+
+fn script(shebang: &str) -> Vec<u8> {
+    let mut script = format!("{shebang}\n");
+    while script.len() < 16 * 1024 {
+        script.push_str("echo \"some line of shell script to make up a body\"\n");
+    }
+    script.into_bytes()
+}
+
+fn round_trip(path: &Path, bytes: &[u8], config: &Configuration) -> Option<Vec<u8>> {
+    let text = String::from_utf8(bytes.to_vec()).unwrap();
+    let result = format_shebang(path, &text, config).unwrap()?;
+    (result != text).then(|| result.into_bytes())
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..FILES {
+        f();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let config = Configuration::default();
+    let path = Path::new("script.sh");
+    for (name, shebang) in [
+        ("canonical", "#!/bin/sh -e"),
+        ("non-canonical", "#! /bin/sh -e"),
+    ] {
+        let bytes = script(shebang);
+        let round_trip = time(|| {
+            black_box(round_trip(path, black_box(&bytes), &config));
+        });
+        let first_line = time(|| {
+            black_box(format_shebang_bytes(path, black_box(&bytes), &config).unwrap());
+        });
+        println!(
+            "{name}: {FILES} files, round trip {round_trip:?}, first line {first_line:?} ({:.1}x)",
+            round_trip.as_secs_f64() / first_line.as_secs_f64()
+        );
+    }
+}
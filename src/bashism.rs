@@ -0,0 +1,116 @@
+use lazy_regex::bytes_regex;
+use lazy_regex::regex::bytes::Regex;
+
+/// A bash feature found in a script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bashism {
+    /// Zero based index of the line it is on.
+    pub line: usize,
+    /// What it is, e.g. `` `[[` ``.
+    pub description: &'static str,
+}
+
+/// Finds the first use of a bash feature not supported by POSIX `sh` in `script`.
+///
+/// This is a heuristic looking for common bashisms line by line: comment lines and here
+/// documents are skipped, but quoting is not understood.
+pub fn find(script: &[u8]) -> Option<Bashism> {
+    let bashisms: [(&'static str, &Regex); 6] = [
+        ("`[[`", bytes_regex!(r"(?:^|[\s;&|(!])\[\[(?:\s|$)")),
+        (
+            "an array",
+            bytes_regex!(r"(?:^|[\s;&|])(?:\w+=\(|(?:declare|local|typeset)\s+-a\b)|\$\{#?\w+\["),
+        ),
+        ("`function`", bytes_regex!(r"^\s*function\s+[\w.-]+")),
+        (
+            "`source`",
+            bytes_regex!(r"(?:^|[;&|]|\b(?:then|do|else))\s*source\s"),
+        ),
+        ("`$'...'`", bytes_regex!(r"\$'")),
+        ("`<<<`", bytes_regex!(r"<<<")),
+    ];
+    let here_document = bytes_regex!(r#"<<(-?)\s*['"]?([A-Za-z_]\w*)['"]?"#);
+
+    let mut lines = script.split(|b| *b == b'\n').enumerate();
+    while let Some((i, line)) = lines.next() {
+        if line.trim_ascii_start().starts_with(b"#") {
+            continue;
+        }
+        if let Some((description, _)) = bashisms.iter().find(|(_, regex)| regex.is_match(line)) {
+            return Some(Bashism {
+                line: i,
+                description,
+            });
+        }
+        if let Some(captures) = here_document.captures(line) {
+            let strip_tabs = !captures[1].is_empty();
+            let delimiter = &captures[2];
+            lines
+                .by_ref()
+                .map(|(_, line)| line.strip_suffix(b"\r").unwrap_or(line))
+                .find(|line| {
+                    let line = if strip_tabs {
+                        line.trim_ascii_start()
+                    } else {
+                        line
+                    };
+                    line == delimiter
+                });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn description(script: &str) -> Option<&'static str> {
+        find(script.as_bytes()).map(|bashism| bashism.description)
+    }
+
+    #[test]
+    fn posix() {
+        let script = "\
+# [[ in a comment, source too
+if [ -n \"$1\" ] && test -f foo; then
+    . ./lib.sh
+    x=$((1 + 2))
+    y=$(echo foo)
+fi
+foo() { echo bar; }
+";
+        assert_eq!(description(script), None);
+    }
+
+    #[test]
+    fn bashisms() {
+        for (script, expected) in [
+            ("if [[ -n $1 ]]; then :; fi", "`[[`"),
+            ("x=(a b c)", "an array"),
+            ("local -a x", "an array"),
+            ("echo ${x[1]}", "an array"),
+            ("function foo {\n:\n}", "`function`"),
+            ("source ./lib.sh", "`source`"),
+            ("[ -f x ] && source ./lib.sh", "`source`"),
+            ("echo $'\\t'", "`$'...'`"),
+            ("cat <<< foo", "`<<<`"),
+        ] {
+            assert_eq!(description(script), Some(expected), "{script}");
+        }
+    }
+
+    #[test]
+    fn line() {
+        let script = "echo foo\n\nif [[ -n $1 ]]; then :; fi\n";
+        assert_eq!(find(script.as_bytes()).unwrap().line, 2);
+    }
+
+    #[test]
+    fn here_document() {
+        let script = "cat <<EOF\nx=(a b)\nEOF\ncat <<-'END'\n\t[[ foo ]]\n\tEND\n";
+        assert_eq!(description(script), None);
+        let script = "cat <<EOF\nfoo\nEOF\nsource foo\n";
+        assert_eq!(find(script.as_bytes()).unwrap().line, 3);
+    }
+}
@@ -0,0 +1,1096 @@
+use crate::pattern::PathGlob;
+use crate::pattern::Pattern;
+use crate::shebang;
+use crate::shebang::LineEnding;
+use crate::version::Version;
+use dprint_core::configuration::ConfigKeyMap;
+use dprint_core::configuration::ConfigKeyValue;
+use dprint_core::configuration::ConfigurationDiagnostic;
+use dprint_core::configuration::GlobalConfiguration;
+use dprint_core::configuration::NewLineKind;
+use dprint_core::configuration::ParseConfigurationError;
+use dprint_core::configuration::ResolveConfigurationResult;
+use dprint_core::configuration::get_nullable_value;
+use dprint_core::configuration::get_nullable_vec;
+use dprint_core::configuration::get_unknown_property_diagnostics;
+use dprint_core::configuration::get_value;
+use dprint_core::configuration::handle_renamed_config_property;
+use dprint_core::generate_str_to_from;
+use dprint_core::plugins::ConfigChange;
+use dprint_core::plugins::ConfigChangeKind;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Resolved plugin configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Configuration {
+    /// Interpreter rewrite rules, applied in order; the first matching one wins.
+    pub interpreter_rewrites: Vec<InterpreterRewrite>,
+    /// Whether to invoke interpreters directly or through `env`.
+    pub env_style: EnvStyle,
+    /// Path to `env`, used when converting to the `env` form.
+    pub env_path: String,
+    /// Whether to add `-S` to `env` shebangs with multiple arguments.
+    pub env_split_string: bool,
+    /// What to do with shebangs passing multiple arguments to the interpreter as one.
+    pub multiple_arguments: MultipleArguments,
+    /// Maximum length of the shebang line in bytes, excluding the line ending.
+    pub max_length: Option<u32>,
+    /// Whether to remove whitespace after the last argument.
+    pub trim_trailing_whitespace: bool,
+    /// Whether to separate arguments with single spaces.
+    pub collapse_whitespace: bool,
+    /// Interpreters not allowed, with optional replacements; the first matching one wins.
+    pub denied_interpreters: Vec<DeniedInterpreter>,
+    /// Whether to replace denied interpreters that have a replacement instead of erroring.
+    pub fix_denied_interpreters: bool,
+    /// Version rules for interpreters by name.
+    pub interpreter_versions: Vec<InterpreterVersion>,
+    /// What to do with `sh` scripts using bash features.
+    pub bashisms: Bashisms,
+    /// Interpreters expected for files by extension or name.
+    pub expected_interpreters: Vec<ExpectedInterpreters>,
+    /// What to do with shebangs not running an expected interpreter.
+    pub interpreter_mismatch: InterpreterMismatch,
+    /// Whether to format files with the default extensions and names.
+    pub default_file_matching: bool,
+    /// Additional file extensions to format, without the leading dot.
+    pub extensions: Vec<String>,
+    /// File extensions not to format, without the leading dot.
+    pub exclude_extensions: Vec<String>,
+    /// Additional file names to format.
+    pub file_names: Vec<String>,
+    /// File names not to format.
+    pub exclude_file_names: Vec<String>,
+    /// Files that must start with a shebang.
+    pub require_shebang: Vec<PathGlob>,
+    /// Shebang lines to insert into files missing one by extension, without the leading dot.
+    pub default_shebangs: BTreeMap<String, String>,
+    /// Files that must not start with a shebang.
+    pub forbid_shebang: Vec<PathGlob>,
+    /// What to do with shebangs in files matching `forbid_shebang`.
+    pub forbidden_shebang: ForbiddenShebang,
+    /// Whether to insert shebangs from `default_shebangs` into all files missing one.
+    pub insert_shebangs: bool,
+    /// Files not to insert shebangs into unless required.
+    pub insert_shebangs_exclude: Vec<PathGlob>,
+    /// What to do with a byte order mark before the shebang.
+    pub byte_order_mark: ByteOrderMark,
+    /// What to do with shebang lines not terminated by a line feed.
+    pub line_ending: LineEndingStyle,
+    /// Absolute paths of interpreters by name, used when converting from the `env` form.
+    pub absolute_paths: BTreeMap<String, String>,
+    /// Whether to have the host format the rest of the file as the language of the interpreter.
+    pub format_body: bool,
+    /// Extensions implying the language of files by interpreter, used when formatting the rest
+    /// of the file; the first matching pattern wins.
+    pub body_extensions: Vec<BodyExtension>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            interpreter_rewrites: Vec::new(),
+            env_style: EnvStyle::Preserve,
+            env_path: String::from("/usr/bin/env"),
+            env_split_string: false,
+            multiple_arguments: MultipleArguments::Allow,
+            max_length: None,
+            trim_trailing_whitespace: false,
+            collapse_whitespace: false,
+            denied_interpreters: Vec::new(),
+            fix_denied_interpreters: false,
+            interpreter_versions: Vec::new(),
+            bashisms: Bashisms::Ignore,
+            expected_interpreters: Vec::new(),
+            interpreter_mismatch: InterpreterMismatch::Error,
+            default_file_matching: true,
+            extensions: Vec::new(),
+            exclude_extensions: Vec::new(),
+            file_names: Vec::new(),
+            exclude_file_names: Vec::new(),
+            require_shebang: Vec::new(),
+            default_shebangs: BTreeMap::new(),
+            forbid_shebang: Vec::new(),
+            forbidden_shebang: ForbiddenShebang::Error,
+            insert_shebangs: false,
+            insert_shebangs_exclude: Vec::new(),
+            byte_order_mark: ByteOrderMark::Ignore,
+            line_ending: LineEndingStyle::Preserve,
+            absolute_paths: BTreeMap::from([
+                (String::from("bash"), String::from("/bin/bash")),
+                (String::from("sh"), String::from("/bin/sh")),
+            ]),
+            format_body: false,
+            body_extensions: [
+                ("node", "js"),
+                ("deno", "ts"),
+                ("python*", "py"),
+                ("sh", "sh"),
+                ("bash", "sh"),
+                ("dash", "sh"),
+                ("ksh", "sh"),
+            ]
+            .into_iter()
+            .map(|(pattern, extension)| BodyExtension {
+                pattern: Pattern::new(pattern).unwrap(),
+                extension: extension.to_string(),
+            })
+            .collect(),
+        }
+    }
+}
+
+/// How interpreters should be invoked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EnvStyle {
+    /// Leave shebangs as they are.
+    Preserve,
+    /// Convert `#!/bin/bash` to `#!/usr/bin/env bash`.
+    PreferEnv,
+    /// Convert `#!/usr/bin/env bash` to `#!/bin/bash`.
+    PreferAbsolute,
+}
+
+generate_str_to_from![
+    EnvStyle,
+    [Preserve, "preserve"],
+    [PreferEnv, "preferEnv"],
+    [PreferAbsolute, "preferAbsolute"]
+];
+
+/// Rewrites interpreters matching `pattern` to `replacement`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct InterpreterRewrite {
+    pub pattern: Pattern,
+    pub replacement: String,
+}
+
+/// Files whose shebang runs a program matching `pattern` are in the language implied by
+/// `extension`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BodyExtension {
+    pub pattern: Pattern,
+    /// Extension without the leading dot.
+    pub extension: String,
+}
+
+/// What to do with shebangs passing multiple arguments to the interpreter.
+///
+/// The kernel passes everything after the interpreter to it as a single argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MultipleArguments {
+    /// Leave them be.
+    Allow,
+    /// Report an error.
+    Error,
+}
+
+generate_str_to_from![MultipleArguments, [Allow, "allow"], [Error, "error"]];
+
+/// Denies interpreters matching `pattern`, suggesting `replacement` instead if set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DeniedInterpreter {
+    pub pattern: Pattern,
+    pub replacement: Option<String>,
+}
+
+/// Version rules for an interpreter.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterpreterVersion {
+    /// Name of the interpreter without a version, e.g. `python`.
+    pub name: String,
+    /// Version to add to the name if it has none.
+    pub default: Option<Version>,
+    /// Oldest version allowed.
+    pub minimum: Option<Version>,
+    /// How much of the version to keep in the name.
+    pub suffix: VersionSuffix,
+}
+
+/// How much of the version to keep in interpreter names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum VersionSuffix {
+    /// Keep it as is, e.g. `python3.11`.
+    Preserve,
+    /// Keep only the major version, e.g. `python3`.
+    Major,
+    /// Remove it, e.g. `python`.
+    None,
+}
+
+generate_str_to_from![
+    VersionSuffix,
+    [Preserve, "preserve"],
+    [Major, "major"],
+    [None, "none"]
+];
+
+/// What to do with `sh` scripts using bash features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Bashisms {
+    /// Leave them be.
+    Ignore,
+    /// Report an error.
+    Error,
+    /// Replace `sh` with `bash` in the shebang.
+    Upgrade,
+}
+
+generate_str_to_from![
+    Bashisms,
+    [Ignore, "ignore"],
+    [Error, "error"],
+    [Upgrade, "upgrade"]
+];
+
+/// Interpreters expected for files matching `files`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ExpectedInterpreters {
+    /// An extension starting with a dot, e.g. `.py`, or a file name, e.g. `Makefile`.
+    pub files: String,
+    /// Patterns matched against the program run by the shebang.
+    pub interpreters: Vec<Pattern>,
+}
+
+impl ExpectedInterpreters {
+    /// Whether `file_name` matches `files`.
+    pub fn matches_file(&self, file_name: &str) -> bool {
+        if self.files.starts_with('.') {
+            file_name.len() > self.files.len() && file_name.ends_with(&self.files)
+        } else {
+            file_name == self.files
+        }
+    }
+}
+
+/// What to do with shebangs not running an expected interpreter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InterpreterMismatch {
+    /// Report an error.
+    Error,
+    /// Replace the interpreter with the first expected one.
+    Fix,
+}
+
+generate_str_to_from![InterpreterMismatch, [Error, "error"], [Fix, "fix"]];
+
+/// What to do with shebangs in files that must not have one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ForbiddenShebang {
+    /// Report an error.
+    Error,
+    /// Remove the shebang line.
+    Remove,
+}
+
+generate_str_to_from![ForbiddenShebang, [Error, "error"], [Remove, "remove"]];
+
+/// What to do with a byte order mark before the shebang.
+///
+/// The kernel does not recognize shebangs preceded by one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ByteOrderMark {
+    /// Leave the file alone.
+    Ignore,
+    /// Remove the byte order mark and format the shebang.
+    Remove,
+    /// Report an error.
+    Error,
+}
+
+generate_str_to_from![
+    ByteOrderMark,
+    [Ignore, "ignore"],
+    [Remove, "remove"],
+    [Error, "error"]
+];
+
+/// What to do with shebang lines not terminated by a line feed.
+///
+/// The kernel only recognizes line feeds, so with CRLF it looks for e.g. `bash\r`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LineEndingStyle {
+    /// Terminate the shebang line with a line feed.
+    Lf,
+    /// Leave the line ending as is.
+    Preserve,
+    /// Report an error.
+    Error,
+}
+
+generate_str_to_from![
+    LineEndingStyle,
+    [Lf, "lf"],
+    [Preserve, "preserve"],
+    [Error, "error"]
+];
+
+/// Renamed properties as `(old, new)` pairs, in the order they were renamed.
+///
+/// Old names keep working with a diagnostic, and `dprint config update` migrates them. Only
+/// properties of released versions belong here; none have been renamed yet.
+const RENAMED_PROPERTIES: &[(&str, &str)] = &[];
+
+/// Resolves the plugin configuration from the `shebang` section of the dprint configuration.
+///
+/// Every known key is consumed from `config`; anything left over is reported as an unknown property.
+pub fn resolve_config(
+    mut config: ConfigKeyMap,
+    global_config: &GlobalConfiguration,
+) -> ResolveConfigurationResult<Configuration> {
+    let mut diagnostics: Vec<ConfigurationDiagnostic> = Vec::new();
+    let defaults = Configuration::default();
+    for (old_key, new_key) in RENAMED_PROPERTIES {
+        handle_renamed_config_property(&mut config, old_key, new_key, &mut diagnostics);
+    }
+
+    let resolved_config = Configuration {
+        interpreter_rewrites: get_pattern_map(&mut config, "interpreterRewrites", &mut diagnostics)
+            .into_iter()
+            .map(|(pattern, replacement)| InterpreterRewrite {
+                pattern,
+                replacement,
+            })
+            .collect(),
+        env_style: get_value(
+            &mut config,
+            "envStyle",
+            defaults.env_style,
+            &mut diagnostics,
+        ),
+        env_path: get_value(&mut config, "envPath", defaults.env_path, &mut diagnostics),
+        env_split_string: get_value(
+            &mut config,
+            "envSplitString",
+            defaults.env_split_string,
+            &mut diagnostics,
+        ),
+        multiple_arguments: get_value(
+            &mut config,
+            "multipleArguments",
+            defaults.multiple_arguments,
+            &mut diagnostics,
+        ),
+        max_length: get_nullable_value(&mut config, "maxLength", &mut diagnostics),
+        trim_trailing_whitespace: get_value(
+            &mut config,
+            "trimTrailingWhitespace",
+            defaults.trim_trailing_whitespace,
+            &mut diagnostics,
+        ),
+        collapse_whitespace: get_value(
+            &mut config,
+            "collapseWhitespace",
+            defaults.collapse_whitespace,
+            &mut diagnostics,
+        ),
+        denied_interpreters: get_pattern_map(&mut config, "deniedInterpreters", &mut diagnostics)
+            .into_iter()
+            .map(|(pattern, replacement)| DeniedInterpreter {
+                pattern,
+                replacement: (!replacement.is_empty()).then_some(replacement),
+            })
+            .collect(),
+        fix_denied_interpreters: get_value(
+            &mut config,
+            "fixDeniedInterpreters",
+            defaults.fix_denied_interpreters,
+            &mut diagnostics,
+        ),
+        interpreter_versions: get_interpreter_versions(
+            &mut config,
+            "interpreterVersions",
+            &mut diagnostics,
+        ),
+        bashisms: get_value(&mut config, "bashisms", defaults.bashisms, &mut diagnostics),
+        expected_interpreters: get_string_map(
+            &mut config,
+            "expectedInterpreters",
+            &mut diagnostics,
+        )
+        .into_iter()
+        .map(|(files, interpreters)| ExpectedInterpreters {
+            interpreters: interpreters
+                .split('|')
+                .filter_map(|pattern| match Pattern::new(pattern.trim()) {
+                    Ok(pattern) => Some(pattern),
+                    Err(err) => {
+                        diagnostics.push(ConfigurationDiagnostic {
+                            property_name: format!("expectedInterpreters.{files}"),
+                            message: format!("Invalid pattern '{pattern}': {err}"),
+                        });
+                        None
+                    }
+                })
+                .collect(),
+            files,
+        })
+        .collect(),
+        interpreter_mismatch: get_value(
+            &mut config,
+            "interpreterMismatch",
+            defaults.interpreter_mismatch,
+            &mut diagnostics,
+        ),
+        default_file_matching: get_value(
+            &mut config,
+            "defaultFileMatching",
+            defaults.default_file_matching,
+            &mut diagnostics,
+        ),
+        extensions: get_extensions(&mut config, "extensions", &mut diagnostics),
+        exclude_extensions: get_extensions(&mut config, "excludeExtensions", &mut diagnostics),
+        file_names: get_string_vec(&mut config, "fileNames", &mut diagnostics),
+        exclude_file_names: get_string_vec(&mut config, "excludeFileNames", &mut diagnostics),
+        require_shebang: get_path_globs(&mut config, "requireShebang", &mut diagnostics),
+        default_shebangs: get_string_map(&mut config, "defaultShebangs", &mut diagnostics)
+            .into_iter()
+            .filter_map(|(extension, line)| {
+                // Inserted lines must parse as a shebang, or they would be inserted again.
+                if shebang::parse(&line)
+                    .is_none_or(|shebang| shebang.line_ending != LineEnding::None)
+                {
+                    diagnostics.push(ConfigurationDiagnostic {
+                        property_name: format!("defaultShebangs.{extension}"),
+                        message: format!("Invalid shebang '{line}'"),
+                    });
+                    return None;
+                }
+                Some((extension.trim_start_matches('.').to_string(), line))
+            })
+            .collect(),
+        forbid_shebang: get_path_globs(&mut config, "forbidShebang", &mut diagnostics),
+        forbidden_shebang: get_value(
+            &mut config,
+            "forbiddenShebang",
+            defaults.forbidden_shebang,
+            &mut diagnostics,
+        ),
+        insert_shebangs: get_value(
+            &mut config,
+            "insertShebangs",
+            defaults.insert_shebangs,
+            &mut diagnostics,
+        ),
+        insert_shebangs_exclude: get_path_globs(
+            &mut config,
+            "insertShebangsExclude",
+            &mut diagnostics,
+        ),
+        byte_order_mark: get_value(
+            &mut config,
+            "byteOrderMark",
+            defaults.byte_order_mark,
+            &mut diagnostics,
+        ),
+        line_ending: get_value(
+            &mut config,
+            "lineEnding",
+            match global_config.new_line_kind {
+                Some(NewLineKind::LineFeed) => LineEndingStyle::Lf,
+                _ => defaults.line_ending,
+            },
+            &mut diagnostics,
+        ),
+        absolute_paths: if config.contains_key("absolutePaths") {
+            get_string_map(&mut config, "absolutePaths", &mut diagnostics)
+                .into_iter()
+                .collect()
+        } else {
+            defaults.absolute_paths
+        },
+        format_body: get_value(
+            &mut config,
+            "formatBody",
+            defaults.format_body,
+            &mut diagnostics,
+        ),
+        body_extensions: if config.contains_key("bodyExtensions") {
+            get_pattern_map(&mut config, "bodyExtensions", &mut diagnostics)
+                .into_iter()
+                .map(|(pattern, extension)| BodyExtension {
+                    pattern,
+                    extension: extension.trim_start_matches('.').to_string(),
+                })
+                .collect()
+        } else {
+            defaults.body_extensions
+        },
+    };
+
+    diagnostics.extend(get_unknown_property_diagnostics(config));
+
+    ResolveConfigurationResult {
+        config: resolved_config,
+        diagnostics,
+    }
+}
+
+/// Returns the changes migrating `config` to the current property names.
+pub fn config_updates(config: &ConfigKeyMap) -> Vec<ConfigChange> {
+    rename_properties(config, RENAMED_PROPERTIES)
+}
+
+/// Returns the changes renaming properties in `config` per `renamed`.
+///
+/// A property renamed again later ends up with its latest name. If the new name is already set,
+/// the old property is just removed.
+fn rename_properties(config: &ConfigKeyMap, renamed: &[(&str, &str)]) -> Vec<ConfigChange> {
+    let mut config = config.clone();
+    let mut changes = Vec::new();
+    for (old_key, new_key) in renamed {
+        let Some(value) = config.shift_remove(*old_key) else {
+            continue;
+        };
+        changes.push(ConfigChange {
+            path: vec![old_key.to_string().into()],
+            kind: ConfigChangeKind::Remove,
+        });
+        if !config.contains_key(*new_key) {
+            changes.push(ConfigChange {
+                path: vec![new_key.to_string().into()],
+                kind: ConfigChangeKind::Add(value.clone()),
+            });
+            config.insert(new_key.to_string(), value);
+        }
+    }
+    changes
+}
+
+/// Takes an array of strings from `config`.
+fn get_string_vec(
+    config: &mut ConfigKeyMap,
+    key: &str,
+    diagnostics: &mut Vec<ConfigurationDiagnostic>,
+) -> Vec<String> {
+    get_nullable_vec(
+        config,
+        key,
+        |value, i, diagnostics| match value {
+            ConfigKeyValue::String(value) => Some(value),
+            _ => {
+                diagnostics.push(ConfigurationDiagnostic {
+                    property_name: format!("{key}[{i}]"),
+                    message: String::from("Expected a string."),
+                });
+                None
+            }
+        },
+        diagnostics,
+    )
+    .unwrap_or_default()
+}
+
+/// Takes an array of path globs from `config`.
+fn get_path_globs(
+    config: &mut ConfigKeyMap,
+    key: &str,
+    diagnostics: &mut Vec<ConfigurationDiagnostic>,
+) -> Vec<PathGlob> {
+    get_string_vec(config, key, diagnostics)
+        .into_iter()
+        .filter_map(|glob| match PathGlob::new(&glob) {
+            Ok(glob) => Some(glob),
+            Err(err) => {
+                diagnostics.push(ConfigurationDiagnostic {
+                    property_name: key.to_string(),
+                    message: format!("Invalid glob '{glob}': {err}"),
+                });
+                None
+            }
+        })
+        .collect()
+}
+
+/// Takes an array of file extensions from `config`, removing leading dots.
+fn get_extensions(
+    config: &mut ConfigKeyMap,
+    key: &str,
+    diagnostics: &mut Vec<ConfigurationDiagnostic>,
+) -> Vec<String> {
+    get_string_vec(config, key, diagnostics)
+        .into_iter()
+        .map(|extension| extension.trim_start_matches('.').to_string())
+        .collect()
+}
+
+/// Takes an object with string values from `config`, preserving key order.
+fn get_string_map(
+    config: &mut ConfigKeyMap,
+    key: &str,
+    diagnostics: &mut Vec<ConfigurationDiagnostic>,
+) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    match config.shift_remove(key) {
+        None | Some(ConfigKeyValue::Null) => {}
+        Some(ConfigKeyValue::Object(values)) => {
+            for (name, value) in values {
+                match value {
+                    ConfigKeyValue::String(value) => result.push((name, value)),
+                    _ => diagnostics.push(ConfigurationDiagnostic {
+                        property_name: format!("{key}.{name}"),
+                        message: String::from("Expected a string."),
+                    }),
+                }
+            }
+        }
+        Some(_) => diagnostics.push(ConfigurationDiagnostic {
+            property_name: key.to_string(),
+            message: String::from("Expected an object."),
+        }),
+    }
+    result
+}
+
+/// Takes an object mapping interpreter names to version rules from `config`.
+fn get_interpreter_versions(
+    config: &mut ConfigKeyMap,
+    key: &str,
+    diagnostics: &mut Vec<ConfigurationDiagnostic>,
+) -> Vec<InterpreterVersion> {
+    let mut result = Vec::new();
+    match config.shift_remove(key) {
+        None | Some(ConfigKeyValue::Null) => {}
+        Some(ConfigKeyValue::Object(values)) => {
+            for (name, value) in values {
+                let ConfigKeyValue::Object(mut rule) = value else {
+                    diagnostics.push(ConfigurationDiagnostic {
+                        property_name: format!("{key}.{name}"),
+                        message: String::from("Expected an object."),
+                    });
+                    continue;
+                };
+                let mut rule_diagnostics = Vec::new();
+                let version = InterpreterVersion {
+                    default: get_nullable_value(&mut rule, "default", &mut rule_diagnostics),
+                    minimum: get_nullable_value(&mut rule, "minimum", &mut rule_diagnostics),
+                    suffix: get_value(
+                        &mut rule,
+                        "suffix",
+                        VersionSuffix::Preserve,
+                        &mut rule_diagnostics,
+                    ),
+                    name,
+                };
+                rule_diagnostics.extend(get_unknown_property_diagnostics(rule));
+                diagnostics.extend(rule_diagnostics.into_iter().map(|diagnostic| {
+                    ConfigurationDiagnostic {
+                        property_name: format!(
+                            "{key}.{}.{}",
+                            version.name, diagnostic.property_name
+                        ),
+                        message: diagnostic.message,
+                    }
+                }));
+                result.push(version);
+            }
+        }
+        Some(_) => diagnostics.push(ConfigurationDiagnostic {
+            property_name: key.to_string(),
+            message: String::from("Expected an object."),
+        }),
+    }
+    result
+}
+
+/// Takes an object mapping patterns to strings from `config`, preserving key order.
+fn get_pattern_map(
+    config: &mut ConfigKeyMap,
+    key: &str,
+    diagnostics: &mut Vec<ConfigurationDiagnostic>,
+) -> Vec<(Pattern, String)> {
+    get_string_map(config, key, diagnostics)
+        .into_iter()
+        .filter_map(|(pattern, value)| match Pattern::new(&pattern) {
+            Ok(pattern) => Some((pattern, value)),
+            Err(err) => {
+                diagnostics.push(ConfigurationDiagnostic {
+                    property_name: key.to_string(),
+                    message: format!("Invalid pattern '{pattern}': {err}"),
+                });
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let result = resolve_config(ConfigKeyMap::new(), &GlobalConfiguration::default());
+        assert!(result.diagnostics.is_empty());
+        assert_eq!(result.config, Configuration::default());
+    }
+
+    #[test]
+    fn unknown_property() {
+        let config =
+            ConfigKeyMap::from([(String::from("fooBar"), ConfigKeyValue::from_bool(true))]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].property_name, "fooBar");
+        assert_eq!(
+            result.diagnostics[0].message,
+            "Unknown property in configuration"
+        );
+    }
+
+    #[test]
+    fn interpreter_rewrites() {
+        let config = ConfigKeyMap::from([(
+            String::from("interpreterRewrites"),
+            ConfigKeyValue::Object(ConfigKeyMap::from([
+                (String::from("python"), ConfigKeyValue::from_str("python3")),
+                (String::from("re:("), ConfigKeyValue::from_str("x")),
+                (String::from("perl"), ConfigKeyValue::from_i32(5)),
+            ])),
+        )]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert_eq!(result.config.interpreter_rewrites.len(), 1);
+        assert_eq!(
+            result.config.interpreter_rewrites[0].pattern.as_str(),
+            "python"
+        );
+        assert_eq!(result.config.interpreter_rewrites[0].replacement, "python3");
+        let properties: Vec<_> = result
+            .diagnostics
+            .iter()
+            .map(|d| d.property_name.as_str())
+            .collect();
+        assert_eq!(
+            properties,
+            ["interpreterRewrites.perl", "interpreterRewrites"]
+        );
+    }
+
+    #[test]
+    fn env_style() {
+        let config = ConfigKeyMap::from([
+            (
+                String::from("envStyle"),
+                ConfigKeyValue::from_str("preferEnv"),
+            ),
+            (
+                String::from("absolutePaths"),
+                ConfigKeyValue::Object(ConfigKeyMap::from([(
+                    String::from("python3"),
+                    ConfigKeyValue::from_str("/usr/bin/python3"),
+                )])),
+            ),
+        ]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert!(result.diagnostics.is_empty());
+        assert_eq!(result.config.env_style, EnvStyle::PreferEnv);
+        assert_eq!(result.config.env_path, "/usr/bin/env");
+        assert_eq!(
+            result.config.absolute_paths,
+            BTreeMap::from([(String::from("python3"), String::from("/usr/bin/python3"))])
+        );
+    }
+
+    #[test]
+    fn expected_interpreters() {
+        let config = ConfigKeyMap::from([(
+            String::from("expectedInterpreters"),
+            ConfigKeyValue::Object(ConfigKeyMap::from([
+                (String::from(".sh"), ConfigKeyValue::from_str("sh | bash")),
+                (String::from("Makefile"), ConfigKeyValue::from_str("make")),
+            ])),
+        )]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert!(result.diagnostics.is_empty());
+        let expected = &result.config.expected_interpreters;
+        assert_eq!(expected.len(), 2);
+        let interpreters: Vec<_> = expected[0]
+            .interpreters
+            .iter()
+            .map(Pattern::as_str)
+            .collect();
+        assert_eq!(interpreters, ["sh", "bash"]);
+        assert!(expected[0].matches_file("foo.sh"));
+        assert!(!expected[0].matches_file(".sh"));
+        assert!(!expected[0].matches_file("foo.bash"));
+        assert!(expected[1].matches_file("Makefile"));
+        assert!(!expected[1].matches_file("foo.Makefile"));
+    }
+
+    #[test]
+    fn denied_interpreters() {
+        let config = ConfigKeyMap::from([(
+            String::from("deniedInterpreters"),
+            ConfigKeyValue::Object(ConfigKeyMap::from([
+                (
+                    String::from("python2*"),
+                    ConfigKeyValue::from_str("python3"),
+                ),
+                (String::from("/usr/bin/perl5"), ConfigKeyValue::from_str("")),
+            ])),
+        )]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert!(result.diagnostics.is_empty());
+        let denied = &result.config.denied_interpreters;
+        assert_eq!(denied.len(), 2);
+        assert_eq!(denied[0].pattern.as_str(), "python2*");
+        assert_eq!(denied[0].replacement.as_deref(), Some("python3"));
+        assert_eq!(denied[1].replacement, None);
+        assert!(!result.config.fix_denied_interpreters);
+    }
+
+    #[test]
+    fn interpreter_versions() {
+        let config = ConfigKeyMap::from([(
+            String::from("interpreterVersions"),
+            ConfigKeyValue::Object(ConfigKeyMap::from([
+                (
+                    String::from("python"),
+                    ConfigKeyValue::Object(ConfigKeyMap::from([
+                        (String::from("default"), ConfigKeyValue::from_i32(3)),
+                        (String::from("minimum"), ConfigKeyValue::from_str("3.11")),
+                        (String::from("suffix"), ConfigKeyValue::from_str("major")),
+                    ])),
+                ),
+                (
+                    String::from("node"),
+                    ConfigKeyValue::Object(ConfigKeyMap::from([
+                        (String::from("minimum"), ConfigKeyValue::from_str("v18")),
+                        (String::from("maximum"), ConfigKeyValue::from_str("20")),
+                    ])),
+                ),
+                (String::from("ruby"), ConfigKeyValue::from_str("3")),
+            ])),
+        )]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        let versions = &result.config.interpreter_versions;
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].name, "python");
+        assert_eq!(versions[0].default, Some("3".parse().unwrap()));
+        assert_eq!(versions[0].minimum, Some("3.11".parse().unwrap()));
+        assert_eq!(versions[0].suffix, VersionSuffix::Major);
+        assert_eq!(versions[1].minimum, None);
+        let properties: Vec<_> = result
+            .diagnostics
+            .iter()
+            .map(|d| d.property_name.as_str())
+            .collect();
+        assert_eq!(
+            properties,
+            [
+                "interpreterVersions.node.minimum",
+                "interpreterVersions.node.maximum",
+                "interpreterVersions.ruby"
+            ]
+        );
+    }
+
+    #[test]
+    fn file_matching() {
+        let config = ConfigKeyMap::from([
+            (
+                String::from("extensions"),
+                ConfigKeyValue::Array(vec![
+                    ConfigKeyValue::from_str(".tcl"),
+                    ConfigKeyValue::from_str("nu"),
+                    ConfigKeyValue::from_bool(true),
+                ]),
+            ),
+            (
+                String::from("fileNames"),
+                ConfigKeyValue::from_str("Justfile"),
+            ),
+        ]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert_eq!(result.config.extensions, ["tcl", "nu"]);
+        assert!(result.config.file_names.is_empty());
+        let properties: Vec<_> = result
+            .diagnostics
+            .iter()
+            .map(|d| d.property_name.as_str())
+            .collect();
+        assert_eq!(properties, ["extensions[2]", "fileNames"]);
+    }
+
+    #[test]
+    fn default_shebangs() {
+        let config = ConfigKeyMap::from([
+            (
+                String::from("requireShebang"),
+                ConfigKeyValue::Array(vec![ConfigKeyValue::from_str("bin/*")]),
+            ),
+            (
+                String::from("defaultShebangs"),
+                ConfigKeyValue::Object(ConfigKeyMap::from([
+                    (String::from(".sh"), ConfigKeyValue::from_str("#!/bin/sh")),
+                    (String::from("py"), ConfigKeyValue::from_str("python3")),
+                    (String::from("pl"), ConfigKeyValue::from_str("#!perl\n")),
+                ])),
+            ),
+        ]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert_eq!(result.config.require_shebang.len(), 1);
+        assert_eq!(
+            result.config.default_shebangs,
+            BTreeMap::from([(String::from("sh"), String::from("#!/bin/sh"))])
+        );
+        let properties: Vec<_> = result
+            .diagnostics
+            .iter()
+            .map(|d| d.property_name.as_str())
+            .collect();
+        assert_eq!(properties, ["defaultShebangs.py", "defaultShebangs.pl"]);
+    }
+
+    #[test]
+    fn line_ending() {
+        let global_config = GlobalConfiguration {
+            new_line_kind: Some(NewLineKind::LineFeed),
+            ..Default::default()
+        };
+        let result = resolve_config(ConfigKeyMap::new(), &global_config);
+        assert_eq!(result.config.line_ending, LineEndingStyle::Lf);
+
+        let config = ConfigKeyMap::from([(
+            String::from("lineEnding"),
+            ConfigKeyValue::from_str("error"),
+        )]);
+        let result = resolve_config(config, &global_config);
+        assert_eq!(result.config.line_ending, LineEndingStyle::Error);
+    }
+
+    #[test]
+    fn max_length() {
+        let config =
+            ConfigKeyMap::from([(String::from("maxLength"), ConfigKeyValue::from_i32(127))]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert!(result.diagnostics.is_empty());
+        assert_eq!(result.config.max_length, Some(127));
+
+        let config =
+            ConfigKeyMap::from([(String::from("maxLength"), ConfigKeyValue::from_i32(-1))]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.config.max_length, None);
+    }
+
+    #[test]
+    fn body_extensions() {
+        let result = resolve_config(ConfigKeyMap::new(), &GlobalConfiguration::default());
+        assert!(!result.config.format_body);
+        assert_eq!(result.config.body_extensions[0].pattern.as_str(), "node");
+
+        let config = ConfigKeyMap::from([
+            (String::from("formatBody"), ConfigKeyValue::from_bool(true)),
+            (
+                String::from("bodyExtensions"),
+                ConfigKeyValue::Object(ConfigKeyMap::from([(
+                    String::from("python3*"),
+                    ConfigKeyValue::from_str(".py"),
+                )])),
+            ),
+        ]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert!(result.diagnostics.is_empty());
+        assert!(result.config.format_body);
+        assert_eq!(result.config.body_extensions.len(), 1);
+        assert_eq!(
+            result.config.body_extensions[0].pattern.as_str(),
+            "python3*"
+        );
+        assert_eq!(result.config.body_extensions[0].extension, "py");
+    }
+
+    #[test]
+    fn schema() {
+        let schema: ConfigKeyMap = serde_json::from_str(include_str!("../schema.json")).unwrap();
+        let Some(ConfigKeyValue::Object(properties)) = schema.get("properties") else {
+            panic!("no properties in schema");
+        };
+        let mut keys = Vec::new();
+        let mut defaults = ConfigKeyMap::new();
+        for (key, property) in properties {
+            // Handled by dprint itself.
+            if ["$schema", "locked", "associations"].contains(&key.as_str()) {
+                continue;
+            }
+            keys.push(key.as_str());
+            let ConfigKeyValue::Object(property) = property else {
+                panic!("{key} is not an object");
+            };
+            if let Some(default) = property.get("default") {
+                defaults.insert(key.clone(), default.clone());
+            }
+            let Some(ConfigKeyValue::Array(variants)) = property.get("oneOf") else {
+                continue;
+            };
+            for variant in variants {
+                let ConfigKeyValue::Object(variant) = variant else {
+                    panic!("{key} variant is not an object");
+                };
+                let config = ConfigKeyMap::from([(key.clone(), variant["const"].clone())]);
+                let result = resolve_config(config, &GlobalConfiguration::default());
+                assert!(result.diagnostics.is_empty(), "{key}: {variant:?}");
+            }
+        }
+
+        let config = serde_json::to_value(Configuration::default()).unwrap();
+        let mut expected: Vec<_> = config.as_object().unwrap().keys().collect();
+        keys.sort();
+        expected.sort();
+        assert_eq!(keys, expected);
+
+        let result = resolve_config(defaults, &GlobalConfiguration::default());
+        assert!(result.diagnostics.is_empty());
+        assert_eq!(result.config, Configuration::default());
+    }
+
+    #[test]
+    fn rename_properties() {
+        let renamed = [("foo", "bar"), ("bar", "baz"), ("quux", "envPath")];
+        let config = ConfigKeyMap::from([
+            (String::from("foo"), ConfigKeyValue::from_bool(true)),
+            (String::from("quux"), ConfigKeyValue::from_str("/bin/env")),
+            (
+                String::from("envPath"),
+                ConfigKeyValue::from_str("/usr/bin/env"),
+            ),
+        ]);
+        let changes: Vec<_> = super::rename_properties(&config, &renamed)
+            .into_iter()
+            .map(|change| serde_json::to_value(change).unwrap())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                serde_json::json!({ "path": ["foo"], "kind": "Remove" }),
+                serde_json::json!({ "path": ["bar"], "kind": "Add", "value": true }),
+                serde_json::json!({ "path": ["bar"], "kind": "Remove" }),
+                serde_json::json!({ "path": ["baz"], "kind": "Add", "value": true }),
+                serde_json::json!({ "path": ["quux"], "kind": "Remove" }),
+            ]
+        );
+        assert!(config_updates(&config).is_empty());
+    }
+
+    #[test]
+    fn invalid_env_style() {
+        let config =
+            ConfigKeyMap::from([(String::from("envStyle"), ConfigKeyValue::from_str("always"))]);
+        let result = resolve_config(config, &GlobalConfiguration::default());
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].property_name, "envStyle");
+        assert_eq!(result.config.env_style, EnvStyle::Preserve);
+    }
+}
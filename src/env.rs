@@ -0,0 +1,294 @@
+use crate::pattern::file_name;
+use crate::shebang::Shebang;
+use crate::shebang::Token;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+/// Arguments of an `env` shebang, e.g. `-S python3 -u` in `#!/usr/bin/env -S python3 -u`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnvArgs {
+    /// Whether `-S` (`--split-string`) is given.
+    pub split_string: bool,
+    /// Options other than `-S` as written, including their values and `--`, e.g. `-i`, `-u`,
+    /// `NAME`.
+    pub options: Vec<String>,
+    /// `NAME=VALUE` environment assignments.
+    pub assignments: Vec<String>,
+    /// The command to run followed by its arguments.
+    pub command: Vec<String>,
+}
+
+/// Options taking a value in a separate argument.
+const OPTIONS_WITH_VALUE: &[&str] = &["-u", "--unset", "-C", "--chdir", "-P"];
+
+impl EnvArgs {
+    /// Parses `env` arguments.
+    ///
+    /// Arguments in a `-S` split string are treated the same as ones outside it, as `env` parses
+    /// both the same way.
+    pub fn parse<'a>(args: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut env_args = Self::default();
+        let mut args: VecDeque<&str> = args.into_iter().collect();
+        let mut end_of_options = false;
+        while let Some(arg) = args.pop_front() {
+            if !env_args.command.is_empty() {
+                env_args.command.push(arg.to_string());
+            } else if end_of_options || !arg.starts_with('-') || arg == "-" {
+                // Options are not recognized after assignments.
+                end_of_options = true;
+                if arg.find('=').is_some_and(|i| i > 0) {
+                    env_args.assignments.push(arg.to_string());
+                } else {
+                    env_args.command.push(arg.to_string());
+                }
+            } else if arg == "-S" || arg == "--split-string" {
+                env_args.split_string = true;
+            } else if let Some(value) = arg
+                .strip_prefix("--split-string=")
+                .or_else(|| arg.strip_prefix("-S"))
+            {
+                env_args.split_string = true;
+                args.push_front(value);
+            } else {
+                env_args.options.push(arg.to_string());
+                if arg == "--" {
+                    end_of_options = true;
+                } else if OPTIONS_WITH_VALUE.contains(&arg)
+                    && let Some(value) = args.pop_front()
+                {
+                    env_args.options.push(value.to_string());
+                }
+            }
+        }
+        env_args
+    }
+
+    /// Number of arguments `env` would be given if split on whitespace.
+    pub fn len(&self) -> usize {
+        usize::from(self.split_string)
+            + self.options.len()
+            + self.assignments.len()
+            + self.command.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the command can be run without `env`: there are no options or assignments, and
+    /// no multiple arguments that only reach the command separately through `-S`.
+    pub fn can_drop_env(&self) -> bool {
+        self.options.is_empty()
+            && self.assignments.is_empty()
+            && !(self.split_string && self.command.len() > 2)
+    }
+
+    /// The arguments in canonical order: `-S`, options, assignments, command.
+    pub fn to_args(&self) -> Vec<String> {
+        let split_string = self.split_string.then(|| String::from("-S"));
+        split_string
+            .into_iter()
+            .chain(self.options.iter().cloned())
+            .chain(self.assignments.iter().cloned())
+            .chain(self.command.iter().cloned())
+            .collect()
+    }
+}
+
+impl Shebang<'_> {
+    /// Parses the arguments if the interpreter is `env`.
+    pub fn env_args(&self) -> Option<EnvArgs> {
+        self.is_env()
+            .then(|| EnvArgs::parse(self.args.iter().map(|arg| arg.value.as_ref())))
+    }
+
+    /// The program the shebang runs: the command for `env`, the interpreter otherwise.
+    ///
+    /// Returns `None` for `env` without a command.
+    pub fn program(&self) -> Option<String> {
+        match self.env_args() {
+            Some(env_args) => env_args.command.into_iter().next(),
+            None => Some(self.interpreter.value.to_string()),
+        }
+    }
+
+    /// Whether the shebang passes arguments to the program it runs, see
+    /// [`program`](Self::program).
+    pub fn has_program_args(&self) -> bool {
+        match self.env_args() {
+            Some(env_args) => env_args.command.len() > 1,
+            None => !self.args.is_empty(),
+        }
+    }
+
+    /// Replaces the program the shebang runs, see [`program`](Self::program).
+    ///
+    /// A `program` containing a slash replaces the interpreter, dropping `env` if used and
+    /// [`EnvArgs::can_drop_env`], or the command otherwise. Other programs only replace the file
+    /// name of the program.
+    pub fn set_program(&mut self, program: &str) {
+        match self.env_args() {
+            Some(env_args) if program.contains('/') && env_args.can_drop_env() => {
+                self.interpreter.value = Cow::Owned(program.to_string());
+                self.args = env_args
+                    .command
+                    .into_iter()
+                    .skip(1)
+                    .map(Token::new)
+                    .collect();
+                self.trailing_space = Cow::Borrowed("");
+            }
+            Some(mut env_args) => {
+                match env_args.command.first_mut() {
+                    Some(command) if !program.contains('/') => {
+                        let dir_len = command.len() - file_name(command).len();
+                        command.replace_range(dir_len.., program);
+                    }
+                    Some(command) => *command = program.to_string(),
+                    None => env_args.command.push(program.to_string()),
+                }
+                self.set_env_args(&env_args);
+            }
+            None if !program.contains('/') => {
+                let name_len = self.interpreter_name().len();
+                let dir_len = self.interpreter.value.len() - name_len;
+                self.interpreter
+                    .value
+                    .to_mut()
+                    .replace_range(dir_len.., program);
+            }
+            None => self.interpreter.value = Cow::Owned(program.to_string()),
+        }
+    }
+
+    /// Replaces the arguments with `env_args`, separated by single spaces.
+    pub fn set_env_args(&mut self, env_args: &EnvArgs) {
+        self.args = env_args.to_args().into_iter().map(Token::new).collect();
+        self.trailing_space = Cow::Borrowed("");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shebang::parse;
+
+    fn env_args(text: &str) -> EnvArgs {
+        parse(text).unwrap().env_args().unwrap()
+    }
+
+    #[test]
+    fn not_env() {
+        assert_eq!(parse("#!/bin/sh -e").unwrap().env_args(), None);
+    }
+
+    #[test]
+    fn program() {
+        assert_eq!(
+            parse("#!/bin/sh -e").unwrap().program().as_deref(),
+            Some("/bin/sh")
+        );
+        assert_eq!(
+            parse("#!/usr/bin/env -S FOO=1 python3 -u")
+                .unwrap()
+                .program()
+                .as_deref(),
+            Some("python3")
+        );
+        assert_eq!(parse("#!/usr/bin/env -i").unwrap().program(), None);
+    }
+
+    #[test]
+    fn has_program_args() {
+        for (text, expected) in [
+            ("#!/bin/sh", false),
+            ("#!/bin/sh -e", true),
+            ("#!/usr/bin/env -S FOO=1 perl", false),
+            ("#!/usr/bin/env -S perl -w", true),
+        ] {
+            assert_eq!(parse(text).unwrap().has_program_args(), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn set_program() {
+        for (text, program, expected) in [
+            ("#!/usr/bin/perl -w", "sh", "#!/usr/bin/sh -w"),
+            ("#!/usr/bin/perl -w", "/bin/sh", "#!/bin/sh -w"),
+            ("#!/usr/bin/env -S perl -w", "sh", "#!/usr/bin/env -S sh -w"),
+            ("#!/usr/bin/env -S perl -w", "/bin/sh", "#!/bin/sh -w"),
+            (
+                "#!/usr/bin/env /opt/bin/perl",
+                "sh",
+                "#!/usr/bin/env /opt/bin/sh",
+            ),
+            (
+                "#!/usr/bin/env -S FOO=1 python2 -u -X dev",
+                "/usr/bin/python3",
+                "#!/usr/bin/env -S FOO=1 /usr/bin/python3 -u -X dev",
+            ),
+            (
+                "#!/usr/bin/env -S PYTHONPATH=lib python2 -u",
+                "/usr/bin/python3",
+                "#!/usr/bin/env -S PYTHONPATH=lib /usr/bin/python3 -u",
+            ),
+            (
+                "#!/usr/bin/env -i python2",
+                "/usr/bin/python3",
+                "#!/usr/bin/env -i /usr/bin/python3",
+            ),
+            (
+                "#!/usr/bin/env -S python2 -u -X dev",
+                "/usr/bin/python3",
+                "#!/usr/bin/env -S /usr/bin/python3 -u -X dev",
+            ),
+        ] {
+            let mut shebang = parse(text).unwrap();
+            shebang.set_program(program);
+            assert_eq!(shebang.to_string(), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn command() {
+        let args = env_args("#!/usr/bin/env python3 -S -u");
+        assert!(!args.split_string);
+        assert_eq!(args.command, ["python3", "-S", "-u"]);
+        assert_eq!(args.len(), 3);
+    }
+
+    #[test]
+    fn split_string() {
+        for text in [
+            "#!/usr/bin/env -S python3 -u",
+            "#!/usr/bin/env -Spython3 -u",
+            "#!/usr/bin/env --split-string=python3 -u",
+        ] {
+            let args = env_args(text);
+            assert!(args.split_string, "{text}");
+            assert_eq!(args.command, ["python3", "-u"], "{text}");
+            assert_eq!(args.to_args(), ["-S", "python3", "-u"], "{text}");
+        }
+    }
+
+    #[test]
+    fn options_and_assignments() {
+        let args = env_args("#!/usr/bin/env -S -i -u HOME -P /opt/bin -- FOO=bar BAZ=1 perl -w");
+        assert!(args.split_string);
+        assert_eq!(args.options, ["-i", "-u", "HOME", "-P", "/opt/bin", "--"]);
+        assert_eq!(args.assignments, ["FOO=bar", "BAZ=1"]);
+        assert_eq!(args.command, ["perl", "-w"]);
+
+        let args = env_args("#!/usr/bin/env FOO=bar -i");
+        assert!(args.options.is_empty());
+        assert_eq!(args.command, ["-i"]);
+    }
+
+    #[test]
+    fn set_env_args() {
+        let mut shebang = parse("#!/usr/bin/env  -S  python3\t -u \n").unwrap();
+        let args = shebang.env_args().unwrap();
+        shebang.set_env_args(&args);
+        assert_eq!(shebang.to_string(), "#!/usr/bin/env -S python3 -u\n");
+    }
+}
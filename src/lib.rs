@@ -1,85 +1,143 @@
 use anyhow::Result;
+use anyhow::bail;
 use dprint_core::configuration::ConfigKeyMap;
+use dprint_core::configuration::ConfigKeyValue;
 use dprint_core::configuration::GlobalConfiguration;
 #[cfg(target_arch = "wasm32")]
 use dprint_core::generate_plugin_code;
 use dprint_core::plugins::FileMatchingInfo;
+use dprint_core::plugins::FormatRange;
 use dprint_core::plugins::FormatResult;
 use dprint_core::plugins::PluginInfo;
 use dprint_core::plugins::PluginResolveConfigurationResult;
 use dprint_core::plugins::SyncFormatRequest;
 use dprint_core::plugins::SyncHostFormatRequest;
 use dprint_core::plugins::SyncPluginHandler;
-use lazy_regex::regex;
-use serde::Serialize;
-use std::cmp;
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+mod bashism;
+mod configuration;
+pub mod env;
+mod pattern;
+pub mod shebang;
+mod version;
+
+pub use configuration::Bashisms;
+pub use configuration::BodyExtension;
+pub use configuration::ByteOrderMark;
+pub use configuration::Configuration;
+pub use configuration::DeniedInterpreter;
+pub use configuration::EnvStyle;
+pub use configuration::ExpectedInterpreters;
+pub use configuration::ForbiddenShebang;
+pub use configuration::InterpreterMismatch;
+pub use configuration::InterpreterRewrite;
+pub use configuration::InterpreterVersion;
+pub use configuration::LineEndingStyle;
+pub use configuration::MultipleArguments;
+pub use configuration::VersionSuffix;
+pub use configuration::resolve_config;
+pub use env::EnvArgs;
+pub use pattern::PathGlob;
+pub use pattern::Pattern;
+pub use shebang::LineEnding;
+pub use shebang::Shebang;
+pub use shebang::Token;
+pub use version::Version;
+
+/// UTF-8 byte order mark.
+const BOM: &str = "\u{feff}";
+
+/// Directories in the default `PATH`, whose interpreters `env` finds the same.
+const STANDARD_PATH_DIRS: &[&str] = &[
+    "/bin",
+    "/sbin",
+    "/usr/bin",
+    "/usr/sbin",
+    "/usr/local/bin",
+    "/usr/local/sbin",
+];
+
+/// File extensions matched unless `defaultFileMatching` is disabled.
+#[rustfmt::skip]
+const DEFAULT_FILE_EXTENSIONS: &[&str] = &[
+    // https://en.wikipedia.org/wiki/AWK
+    "awk",
+    // https://bats-core.readthedocs.io
+    "bats",
+    // https://en.wikipedia.org/wiki/Common_Gateway_Interface
+    "cgi",
+    // https://dlang.org/rdmd.html
+    "d",
+    // https://elixir-lang.org
+    "exs",
+    // https://openjdk.org/jeps/330#Shebang_files
+    "java",
+    // https://nodejs.org/en/learn/command-line/run-nodejs-scripts-from-the-command-line
+    "js", "ts",
+    // https://github.com/Kotlin/KEEP/blob/main/proposals/KEEP-0075-scripting-support.md
+    "kts",
+    // https://www.lua.org
+    "lua",
+    // https://en.wikipedia.org/wiki/Make_(software)
+    "mk",
+    // https://www.php.net/manual/en/features.commandline.usage.php
+    "php", "php3", "php4", "php5",
+    // https://perldoc.perl.org/perlrun#Location-of-Perl
+    "pl", "t", "perl",
+    // https://www.debian.org/doc/debian-policy/ch-maintainerscripts.html
+    "postinst", "postrm", "preinst", "prerm",
+    // https://learn.microsoft.com/en-us/powershell/module/microsoft.powershell.core/about/about_comments#shebang
+    "ps1",
+    // https://docs.python.org/3/using/unix.html#miscellaneous
+    "py",
+    // https://www.ruby-lang.org
+    "rb",
+    // https://www.gnu.org/software/sed
+    "sed",
+    // https://en.wikipedia.org/wiki/Shell_script
+    "sh", "bash", "csh", "fish", "ksh", "tcsh", "zsh",
+    // https://www.slackwiki.com/Writing_A_SlackBuild_Script
+    "SlackBuild",
+    // https://sourceware.org/systemtap/SystemTap_Beginners_Guide/useful-systemtap-scripts.html
+    "stp",
+];
+
+/// File names matched unless `defaultFileMatching` is disabled.
+#[rustfmt::skip]
+const DEFAULT_FILE_NAMES: &[&str] = &[
+    // https://en.wikipedia.org/wiki/Make_(software)
+    "Makefile", "GNUmakefile",
+];
 
 #[derive(Default)]
 pub struct ShebangPluginHandler;
 
-#[derive(Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Configuration {}
+impl ShebangPluginHandler {
+    /// Whether the file is a script this plugin should handle: one starting with `#!`, possibly
+    /// preceded by a byte order mark, or one a missing shebang is to be inserted into.
+    ///
+    /// Files routed to the plugin with dprint `associations`, e.g. everything in `bin/`, are
+    /// left alone unless this holds.
+    pub fn is_script(file_path: &Path, file_bytes: &[u8], config: &Configuration) -> bool {
+        starts_with_shebang(file_bytes) || wants_shebang(file_path, config)
+    }
+}
 
 impl SyncPluginHandler<Configuration> for ShebangPluginHandler {
     fn resolve_config(
         &mut self,
-        _config: ConfigKeyMap,
-        _global_config: &GlobalConfiguration,
+        config: ConfigKeyMap,
+        global_config: &GlobalConfiguration,
     ) -> PluginResolveConfigurationResult<Configuration> {
+        let result = resolve_config(config, global_config);
         PluginResolveConfigurationResult {
-            config: Configuration {},
-            diagnostics: Vec::new(),
-            file_matching: FileMatchingInfo {
-                #[rustfmt::skip]
-                file_extensions: [
-                    // https://en.wikipedia.org/wiki/AWK
-                    "awk",
-                    // https://bats-core.readthedocs.io
-                    "bats",
-                    // https://en.wikipedia.org/wiki/Common_Gateway_Interface
-                    "cgi",
-                    // https://dlang.org/rdmd.html
-                    "d",
-                    // https://elixir-lang.org
-                    "exs",
-                    // https://openjdk.org/jeps/330#Shebang_files
-                    "java",
-                    // https://nodejs.org/en/learn/command-line/run-nodejs-scripts-from-the-command-line
-                    "js", "ts",
-                    // https://github.com/Kotlin/KEEP/blob/main/proposals/KEEP-0075-scripting-support.md
-                    "kts",
-                    // https://www.lua.org
-                    "lua",
-                    // https://en.wikipedia.org/wiki/Make_(software)
-                    "mk",
-                    // https://www.php.net/manual/en/features.commandline.usage.php
-                    "php", "php3", "php4", "php5",
-                    // https://perldoc.perl.org/perlrun#Location-of-Perl
-                    "pl", "t", "perl",
-                    // https://www.debian.org/doc/debian-policy/ch-maintainerscripts.html
-                    "postinst", "postrm", "preinst", "prerm",
-                    // https://learn.microsoft.com/en-us/powershell/module/microsoft.powershell.core/about/about_comments#shebang
-                    "ps1",
-                    // https://docs.python.org/3/using/unix.html#miscellaneous
-                    "py",
-                    // https://www.ruby-lang.org
-                    "rb",
-                    // https://www.gnu.org/software/sed
-                    "sed",
-                    // https://en.wikipedia.org/wiki/Shell_script
-                    "sh", "bash", "csh", "fish", "ksh", "tcsh", "zsh",
-                    // https://www.slackwiki.com/Writing_A_SlackBuild_Script
-                    "SlackBuild",
-                    // https://sourceware.org/systemtap/SystemTap_Beginners_Guide/useful-systemtap-scripts.html
-                    "stp",
-                ].into_iter().map(String::from).collect(),
-                #[rustfmt::skip]
-                file_names: vec![
-                    // https://en.wikipedia.org/wiki/Make_(software)
-                    "Makefile", "GNUmakefile",
-                ].into_iter().map(String::from).collect(),
-            },
+            file_matching: file_matching_info(&result.config),
+            config: result.config,
+            diagnostics: result.diagnostics,
         }
     }
 
@@ -89,7 +147,10 @@ impl SyncPluginHandler<Configuration> for ShebangPluginHandler {
             version: env!("CARGO_PKG_VERSION").to_string(),
             config_key: "shebang".to_string(),
             help_url: "https://github.com/scop/dprint-plugin-shebang".to_string(),
-            config_schema_url: "".to_string(),
+            config_schema_url: format!(
+                "https://plugins.dprint.dev/scop/shebang/{}/schema.json",
+                env!("CARGO_PKG_VERSION")
+            ),
             update_url: Some("https://plugins.dprint.dev/scop/shebang/latest.json".to_string()),
         }
     }
@@ -100,106 +161,763 @@ impl SyncPluginHandler<Configuration> for ShebangPluginHandler {
 
     fn check_config_updates(
         &self,
-        _message: dprint_core::plugins::CheckConfigUpdatesMessage,
+        message: dprint_core::plugins::CheckConfigUpdatesMessage,
     ) -> Result<Vec<dprint_core::plugins::ConfigChange>> {
-        Ok(Vec::new())
+        Ok(configuration::config_updates(&message.config))
     }
 
     fn format(
         &mut self,
         request: SyncFormatRequest<Configuration>,
-        _format_with_host: impl FnMut(SyncHostFormatRequest) -> FormatResult,
+        mut format_with_host: impl FnMut(SyncHostFormatRequest) -> FormatResult,
     ) -> FormatResult {
-        let bytes = if request.range.is_some() {
-            let range = request.range.unwrap();
-            if range.start != 0 {
-                return Ok(None);
+        if !Self::is_script(request.file_path, &request.file_bytes, request.config) {
+            return Ok(None);
+        }
+
+        // Ranges not touching the first line leave the shebang alone.
+        let line_end = first_line_end(&request.file_bytes);
+        let formatted = match &request.range {
+            Some(range) if range.start >= line_end && range.start > 0 => None,
+            _ => format_shebang_bytes(request.file_path, &request.file_bytes, request.config)?,
+        };
+        if !request.config.format_body {
+            return Ok(formatted);
+        }
+
+        // The body is a suffix of the file: everything after the shebang line, or the whole
+        // file, less any byte order mark, if one was inserted.
+        let file_bytes = formatted.as_deref().unwrap_or(&request.file_bytes);
+        let body_start = request.file_bytes.len() - (file_bytes.len() - first_line_end(file_bytes));
+        let body_range = match request.range {
+            Some(range) if range.end <= body_start => return Ok(formatted),
+            Some(range) => Some(range.start.saturating_sub(body_start)..range.end - body_start),
+            None => None,
+        };
+        let result = format_body(
+            request.file_path,
+            file_bytes,
+            body_range,
+            request.config,
+            &mut format_with_host,
+        )?;
+        Ok(result.or(formatted))
+    }
+}
+
+/// Computes the files to format from the defaults and the configuration.
+fn file_matching_info(config: &Configuration) -> FileMatchingInfo {
+    let merge = |defaults: &[&str], include: &[String], exclude: &[String]| {
+        let defaults = defaults
+            .iter()
+            .filter(|_| config.default_file_matching)
+            .map(|s| s.to_string());
+        let mut result: Vec<String> = Vec::new();
+        for item in defaults.chain(include.iter().cloned()) {
+            if !exclude.contains(&item) && !result.contains(&item) {
+                result.push(item);
             }
-            request.file_bytes[range.start..range.end].to_vec()
-        } else {
-            request.file_bytes
+        }
+        result
+    };
+    FileMatchingInfo {
+        file_extensions: merge(
+            DEFAULT_FILE_EXTENSIONS,
+            &config.extensions,
+            &config.exclude_extensions,
+        ),
+        file_names: merge(
+            DEFAULT_FILE_NAMES,
+            &config.file_names,
+            &config.exclude_file_names,
+        ),
+    }
+}
+
+/// Whether `bytes` start with `#!`, possibly preceded by a byte order mark.
+fn starts_with_shebang(bytes: &[u8]) -> bool {
+    bytes
+        .strip_prefix(BOM.as_bytes())
+        .unwrap_or(bytes)
+        .starts_with(b"#!")
+}
+
+/// Returns the end of the first line in `bytes`, including its terminator.
+fn first_line_end(bytes: &[u8]) -> usize {
+    match bytes.iter().position(|b| *b == b'\n' || *b == b'\r') {
+        Some(i) if bytes[i..].starts_with(b"\r\n") => i + 2,
+        Some(i) => i + 1,
+        None => bytes.len(),
+    }
+}
+
+/// Whether the first line in `bytes` is text: valid UTF-8 without NUL bytes.
+fn first_line_is_text(bytes: &[u8]) -> bool {
+    std::str::from_utf8(&bytes[..first_line_end(bytes)]).is_ok_and(|line| !line.contains('\0'))
+}
+
+/// Formats the shebang in `file_bytes`, inserting one if it is missing and required.
+///
+/// Only the first line is decoded and touched; the rest of the file may be in any encoding, or
+/// not text at all. Returns `None` if there is no shebang or it is already formatted, without
+/// copying the file; with the default configuration, parsing the line into tokens is then the
+/// only allocation.
+pub fn format_shebang_bytes(
+    file_path: &Path,
+    file_bytes: &[u8],
+    config: &Configuration,
+) -> Result<Option<Vec<u8>>> {
+    let (line, rest) = file_bytes.split_at(first_line_end(file_bytes));
+    let line = match std::str::from_utf8(line) {
+        Ok(line) => line,
+        Err(_) if starts_with_shebang(line) => {
+            bail!("Shebang line is not valid UTF-8")
+        }
+        Err(_) => return insert_shebang(file_path, file_bytes, config),
+    };
+    let Some((shebang, _)) = format_line(file_path, line, rest, config)? else {
+        return insert_shebang(file_path, file_bytes, config);
+    };
+    let Some(shebang) = shebang else {
+        return Ok(Some(rest.to_vec()));
+    };
+    if shebang.renders_as(line) {
+        return Ok(None);
+    }
+    let line_length = shebang.line_length() + shebang.line_ending.as_str().len();
+    let mut result = Vec::with_capacity(line_length + rest.len());
+    write!(result, "{shebang}")?;
+    result.extend_from_slice(rest);
+    Ok(Some(result))
+}
+
+/// Whether the file should start with a shebang: it does not match `config.forbid_shebang`,
+/// and it matches `config.require_shebang`, or `config.insert_shebangs` is set, there is a
+/// default shebang for it, and it does not match `config.insert_shebangs_exclude`.
+fn wants_shebang(file_path: &Path, config: &Configuration) -> bool {
+    if config
+        .forbid_shebang
+        .iter()
+        .any(|glob| glob.is_match(file_path))
+    {
+        return false;
+    }
+    config
+        .require_shebang
+        .iter()
+        .any(|glob| glob.is_match(file_path))
+        || (config.insert_shebangs
+            && default_shebang(file_path, config).is_some()
+            && !config
+                .insert_shebangs_exclude
+                .iter()
+                .any(|glob| glob.is_match(file_path)))
+}
+
+/// Returns the shebang from `config.default_shebangs` for the extension of the file.
+fn default_shebang<'a>(file_path: &Path, config: &'a Configuration) -> Option<&'a str> {
+    let extension = file_path.extension()?.to_str()?;
+    config.default_shebangs.get(extension).map(String::as_str)
+}
+
+/// Inserts the shebang from `config.default_shebangs` for the extension of the file if it
+/// has none and should have one per [`wants_shebang`], or errors if there is no default for it.
+///
+/// Files whose first line is not text, i.e. not valid UTF-8 or containing NUL bytes, are left
+/// alone, as they are likely binaries. A byte order mark would end up before the second line,
+/// so it is handled per `config.byte_order_mark` as one before a shebang. The inserted shebang
+/// is formatted like any other.
+fn insert_shebang(
+    file_path: &Path,
+    file_bytes: &[u8],
+    config: &Configuration,
+) -> Result<Option<Vec<u8>>> {
+    if starts_with_shebang(file_bytes)
+        || !first_line_is_text(file_bytes)
+        || !wants_shebang(file_path, config)
+    {
+        return Ok(None);
+    }
+    let file_bytes = match file_bytes.strip_prefix(BOM.as_bytes()) {
+        Some(rest) => match config.byte_order_mark {
+            ByteOrderMark::Ignore => return Ok(None),
+            ByteOrderMark::Remove => rest,
+            ByteOrderMark::Error => {
+                bail!("Byte order mark at the start of the file prevents inserting a shebang")
+            }
+        },
+        None => file_bytes,
+    };
+    let Some(line) = default_shebang(file_path, config) else {
+        bail!("File has no shebang, but one is required by requireShebang");
+    };
+    let mut result = Vec::with_capacity(line.len() + 1 + file_bytes.len());
+    result.extend_from_slice(line.as_bytes());
+    result.push(b'\n');
+    result.extend_from_slice(file_bytes);
+    Ok(Some(
+        format_shebang_bytes(file_path, &result, config)?.unwrap_or(result),
+    ))
+}
+
+/// Has the host format everything after the shebang line as the language implied by the
+/// program the shebang runs, per `config.body_extensions`.
+///
+/// The host is given the path of the file with the implied extension appended, so other
+/// plugins pick it up even for extensionless scripts. As that path may be routed back to this
+/// plugin, inserting shebangs and formatting bodies are turned off for it. `range` is relative
+/// to the start of the body. Returns `None` if there is no shebang, no extension for its
+/// program, or nothing changed.
+fn format_body(
+    file_path: &Path,
+    file_bytes: &[u8],
+    range: FormatRange,
+    config: &Configuration,
+    format_with_host: &mut impl FnMut(SyncHostFormatRequest) -> FormatResult,
+) -> FormatResult {
+    let (line, body) = file_bytes.split_at(first_line_end(file_bytes));
+    if body.is_empty() {
+        return Ok(None);
+    }
+    let Ok(line) = std::str::from_utf8(line) else {
+        return Ok(None);
+    };
+    let Some(shebang) = shebang::parse(line.strip_prefix(BOM).unwrap_or(line)) else {
+        return Ok(None);
+    };
+    let Some(program) = shebang.program() else {
+        return Ok(None);
+    };
+    let Some(extension) = config
+        .body_extensions
+        .iter()
+        .find(|body_extension| body_extension.pattern.is_match(&program))
+        .map(|body_extension| body_extension.extension.as_str())
+    else {
+        return Ok(None);
+    };
+    let Some(body) = format_with_host(SyncHostFormatRequest {
+        file_path: &body_path(file_path, extension),
+        file_bytes: body,
+        range,
+        override_config: &ConfigKeyMap::from([
+            (
+                String::from("requireShebang"),
+                ConfigKeyValue::Array(Vec::new()),
+            ),
+            (String::from("insertShebangs"), ConfigKeyValue::Bool(false)),
+            (String::from("formatBody"), ConfigKeyValue::Bool(false)),
+        ]),
+    })?
+    else {
+        return Ok(None);
+    };
+    let mut result = Vec::with_capacity(line.len() + body.len());
+    result.extend_from_slice(line.as_bytes());
+    result.extend_from_slice(&body);
+    Ok(Some(result))
+}
+
+/// Returns `file_path` with `extension` appended, unless it already has it.
+fn body_path(file_path: &Path, extension: &str) -> PathBuf {
+    if file_path
+        .extension()
+        .is_some_and(|file_extension| file_extension == extension)
+    {
+        return file_path.to_path_buf();
+    }
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Formats the shebang in `text`, inserting one if it is missing and required.
+///
+/// Returns the whole formatted text, or `None` if there is no shebang.
+pub fn format_shebang(
+    file_path: &Path,
+    text: &str,
+    config: &Configuration,
+) -> Result<Option<String>> {
+    let body = &text.as_bytes()[first_line_end(text.as_bytes())..];
+    let Some((shebang, end)) = format_line(file_path, text, body, config)? else {
+        return match insert_shebang(file_path, text.as_bytes(), config)? {
+            Some(result) => Ok(Some(String::from_utf8(result)?)),
+            None => Ok(None),
         };
+    };
+    match shebang {
+        Some(shebang) => Ok(Some(format!("{}{}", shebang, &text[end..]))),
+        None => Ok(Some(text[end..].to_string())),
+    }
+}
 
-        let text = String::from_utf8(bytes)?;
-        let result = format_shebang(&text)?;
-        if result.is_none() {
-            return Ok(None);
+/// Parses and formats the shebang on the first line of `text`, followed by `body` in the file.
+///
+/// Returns the formatted shebang, or `None` if it is to be removed, along with the length of
+/// the part of `text` it replaces. Returns `None` if there is no shebang.
+fn format_line<'a>(
+    file_path: &Path,
+    text: &'a str,
+    body: &[u8],
+    config: &Configuration,
+) -> Result<Option<(Option<Shebang<'a>>, usize)>> {
+    let start = match text.strip_prefix(BOM) {
+        Some(rest) if rest.starts_with("#!") => match config.byte_order_mark {
+            ByteOrderMark::Ignore => return Ok(None),
+            ByteOrderMark::Remove => BOM.len(),
+            ByteOrderMark::Error => {
+                bail!("Byte order mark before the shebang prevents the kernel from recognizing it")
+            }
+        },
+        _ => 0,
+    };
+    let Some(mut shebang) = shebang::parse(&text[start..]) else {
+        return Ok(None);
+    };
+    if config
+        .forbid_shebang
+        .iter()
+        .any(|glob| glob.is_match(file_path))
+    {
+        match config.forbidden_shebang {
+            ForbiddenShebang::Error => bail!("File has a shebang, but is matched by forbidShebang"),
+            ForbiddenShebang::Remove => return Ok(Some((None, start + shebang.span.end))),
         }
+    }
+    rewrite_interpreter(&mut shebang, config);
+    apply_env_style(&mut shebang, config);
+    format_env_args(&mut shebang, config);
+    check_denied_interpreter(&mut shebang, config)?;
+    apply_interpreter_version(&mut shebang, config)?;
+    check_bashisms(&mut shebang, body, config)?;
+    check_expected_interpreter(&mut shebang, file_path, config)?;
+    shebang.normalize_whitespace();
+    if config.collapse_whitespace {
+        shebang.collapse_whitespace();
+    }
+    if config.trim_trailing_whitespace {
+        shebang.trim_trailing_whitespace();
+    }
+    format_line_ending(&mut shebang, config)?;
+    check_multiple_arguments(&shebang, config)?;
+    check_max_length(&shebang, config)?;
+    let end = start + shebang.span.end;
+    Ok(Some((Some(shebang), end)))
+}
 
-        let result = result.unwrap();
-        if result == text {
-            Ok(None)
-        } else {
-            Ok(Some(result.into_bytes()))
+/// Applies the first matching interpreter rewrite rule, if any.
+///
+/// Arguments in the replacement, e.g. `python3` in `/usr/bin/env python3`, are inserted before
+/// the existing ones.
+fn rewrite_interpreter(shebang: &mut Shebang, config: &Configuration) {
+    let Some(replacement) = config.interpreter_rewrites.iter().find_map(|rule| {
+        rule.pattern
+            .replace(&shebang.interpreter.value, &rule.replacement)
+    }) else {
+        return;
+    };
+    let mut values = replacement.split([' ', '\t']).filter(|s| !s.is_empty());
+    let Some(interpreter) = values.next() else {
+        return;
+    };
+    shebang.interpreter.value = Cow::Owned(interpreter.to_string());
+    shebang
+        .args
+        .splice(0..0, values.map(|value| Token::new(value.to_string())));
+}
+
+/// Converts between the direct and `env` forms of invoking an interpreter, per
+/// `config.env_style`.
+///
+/// Interpreters with arguments are converted to the `env` form only if `config.env_split_string`
+/// is set, as the kernel would otherwise pass the interpreter and its arguments to `env` as one.
+fn apply_env_style(shebang: &mut Shebang, config: &Configuration) {
+    match config.env_style {
+        EnvStyle::Preserve => {}
+        EnvStyle::PreferEnv => {
+            if shebang.is_env() || (!shebang.args.is_empty() && !config.env_split_string) {
+                return;
+            }
+            // Interpreters elsewhere, e.g. in a virtual environment, may differ from the ones
+            // `env` would find.
+            let name = shebang.interpreter_name().to_string();
+            let interpreter = shebang.interpreter.value.as_ref();
+            let dir = &interpreter[..interpreter.len() - name.len()];
+            if !STANDARD_PATH_DIRS.contains(&dir.trim_end_matches('/'))
+                && config.absolute_paths.get(&name).map(String::as_str) != Some(interpreter)
+            {
+                return;
+            }
+            shebang.interpreter.value = Cow::Owned(config.env_path.clone());
+            shebang.args.insert(0, Token::new(name));
         }
+        EnvStyle::PreferAbsolute => {
+            let Some(env_args) = shebang.env_args() else {
+                return;
+            };
+            if !env_args.can_drop_env() {
+                return;
+            }
+            let Some((command, args)) = env_args.command.split_first() else {
+                return;
+            };
+            if let Some(path) = config.absolute_paths.get(command) {
+                shebang.interpreter.value = Cow::Owned(path.clone());
+                shebang.args.drain(..shebang.args.len() - args.len());
+            }
+        }
+    }
+}
+
+/// Normalizes `env` arguments, adding `-S` if `config.env_split_string` is set and there are
+/// multiple arguments. [Quoted](Shebang::has_quoted_args) arguments are left alone.
+fn format_env_args(shebang: &mut Shebang, config: &Configuration) {
+    // Parsing the arguments allocates, so it is skipped unless there is something to do.
+    let splits = || {
+        shebang
+            .args
+            .iter()
+            .any(|arg| arg.value.starts_with("-S") || arg.value.starts_with("--split-string"))
+    };
+    if !(config.env_split_string || splits()) || shebang.has_quoted_args() {
+        return;
+    }
+    let Some(mut env_args) = shebang.env_args() else {
+        return;
+    };
+    if config.env_split_string && env_args.len() > 1 {
+        env_args.split_string = true;
+    }
+    if env_args.split_string {
+        shebang.set_env_args(&env_args);
     }
 }
 
-pub fn format_shebang(text: &str) -> Result<Option<String>> {
-    let re = regex!(
-        r#"^#!(?x)                          # hashbang
-        [\ \t]*                             # optional whitespace
-        (?<interpreter>[^\ \t]+)            # interpreter
-        (?:[\ \t]*?                         # optional whitespace
-          |[\ \t]+(?<args>[^\ \t][^\r\n]*?) # optional whitespace, then args; note that whitespace after args is part of args, not stripped
-        )?
-        (?<end>[\r\n]|$)                    # end of line
-    "#
-    );
-    if let Some(captures) = re.captures(&text[..cmp::min(text.len(), 1024)]) {
-        let end = captures.name("end").unwrap().start();
-        let interpreter = captures.name("interpreter").unwrap().as_str();
-        let args = captures.name("args").map_or("", |m| m.as_str());
-        if args.is_empty() {
-            return Ok(Some(String::from(&format!(
-                "#!{}{}",
-                interpreter,
-                &text[end..]
-            ))));
+/// Errors if the program the shebang runs is denied by `config.denied_interpreters`, or
+/// replaces it if `config.fix_denied_interpreters` is set and the rule has a replacement.
+///
+/// Replacements are applied as in `config.interpreter_rewrites`, but to the program rather
+/// than the interpreter, so they work the same for `env` shebangs.
+fn check_denied_interpreter(shebang: &mut Shebang, config: &Configuration) -> Result<()> {
+    if config.denied_interpreters.is_empty() {
+        return Ok(());
+    }
+    let Some(program) = shebang.program() else {
+        return Ok(());
+    };
+    let Some(rule) = config
+        .denied_interpreters
+        .iter()
+        .find(|rule| rule.pattern.is_match(&program))
+    else {
+        return Ok(());
+    };
+    let replacement = rule
+        .replacement
+        .as_ref()
+        .and_then(|replacement| rule.pattern.replace(&program, replacement));
+    match replacement {
+        Some(replacement) if config.fix_denied_interpreters => {
+            replace_program(shebang, Some(&program), &replacement)
+        }
+        Some(replacement) => {
+            bail!("Shebang runs {program}, which is not allowed; use {replacement} instead")
         }
-        return Ok(Some(String::from(&format!(
-            "#!{} {}{}",
-            interpreter,
-            args,
-            &text[end..]
-        ))));
+        None => bail!("Shebang runs {program}, which is not allowed"),
+    }
+}
+
+/// Adds, checks and trims the version suffix of the program the shebang runs, per
+/// `config.interpreter_versions`.
+fn apply_interpreter_version(shebang: &mut Shebang, config: &Configuration) -> Result<()> {
+    if config.interpreter_versions.is_empty() {
+        return Ok(());
+    }
+    let Some(program) = shebang.program() else {
+        return Ok(());
+    };
+    let program_name = pattern::file_name(&program);
+    let (name, suffix) = version::split_version(program_name);
+    let Some(rule) = config
+        .interpreter_versions
+        .iter()
+        .find(|rule| rule.name == name)
+    else {
+        return Ok(());
+    };
+    let version = match suffix.parse::<Version>() {
+        Ok(version) => Some(version),
+        Err(_) => rule.default.clone(),
+    };
+    if let Some(version) = &version
+        && let Some(minimum) = &rule.minimum
+        && version.is_older_than(minimum)
+    {
+        bail!("Shebang runs {name}{version}, older than the minimum version {minimum}");
+    }
+    let suffix = match (rule.suffix, version) {
+        (VersionSuffix::None, _) | (_, None) => String::new(),
+        (VersionSuffix::Major, Some(version)) => version.major().to_string(),
+        (VersionSuffix::Preserve, Some(_)) if !suffix.is_empty() => suffix.to_string(),
+        (VersionSuffix::Preserve, Some(version)) => version.to_string(),
+    };
+    let new_name = format!("{name}{suffix}");
+    if new_name != program_name {
+        shebang.set_program(&new_name);
+    }
+    Ok(())
+}
+
+/// Errors or switches to `bash` per `config.bashisms` if the shebang runs `sh` and `body`
+/// uses bash features.
+fn check_bashisms(shebang: &mut Shebang, body: &[u8], config: &Configuration) -> Result<()> {
+    if config.bashisms == Bashisms::Ignore
+        || shebang
+            .program()
+            .is_none_or(|program| pattern::file_name(&program) != "sh")
+    {
+        return Ok(());
+    }
+    let Some(bashism) = bashism::find(body) else {
+        return Ok(());
+    };
+    match config.bashisms {
+        Bashisms::Ignore => {}
+        Bashisms::Error => bail!(
+            "Shebang runs sh, but line {} uses {}, which needs bash",
+            bashism.line + 2,
+            bashism.description
+        ),
+        Bashisms::Upgrade => shebang.set_program("bash"),
+    }
+    Ok(())
+}
+
+/// Checks that the shebang runs an interpreter expected for the file per
+/// `config.expected_interpreters`, and fixes or errors if not per `config.interpreter_mismatch`.
+///
+/// Fixing requires the first expected interpreter to be a plain name or path.
+fn check_expected_interpreter(
+    shebang: &mut Shebang,
+    file_path: &Path,
+    config: &Configuration,
+) -> Result<()> {
+    let Some(file_name) = file_path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(());
+    };
+    let Some(expected) = config
+        .expected_interpreters
+        .iter()
+        .find(|expected| expected.matches_file(file_name))
+    else {
+        return Ok(());
+    };
+    let program = shebang.program();
+    if let Some(program) = &program
+        && expected
+            .interpreters
+            .iter()
+            .any(|pattern| pattern.is_match(program))
+    {
+        return Ok(());
+    }
+    let replacement = match config.interpreter_mismatch {
+        InterpreterMismatch::Error => None,
+        InterpreterMismatch::Fix => expected.interpreters.first().and_then(Pattern::literal),
+    };
+    let Some(replacement) = replacement else {
+        let interpreters: Vec<_> = expected.interpreters.iter().map(Pattern::as_str).collect();
+        bail!(
+            "Shebang runs {}, expected {} for {}",
+            program.as_deref().unwrap_or(&shebang.interpreter.value),
+            interpreters.join(" or "),
+            file_name
+        );
+    };
+    replace_program(shebang, program.as_deref(), replacement)
+}
+
+/// Replaces `program`, the one the shebang runs, with `replacement`.
+///
+/// Arguments are meant for the program they are passed to, so replacing a program passed any
+/// with a different one, other than another version of it, errors instead.
+fn replace_program(shebang: &mut Shebang, program: Option<&str>, replacement: &str) -> Result<()> {
+    let name = |program| version::split_version(pattern::file_name(program)).0;
+    if let Some(program) = program
+        && name(program) != name(replacement)
+        && shebang.has_program_args()
+    {
+        bail!(
+            "Shebang runs {program} with arguments that may not apply to {replacement}; \
+             replace it manually"
+        );
+    }
+    shebang.set_program(replacement);
+    Ok(())
+}
+
+/// Terminates the shebang line with a line feed, or errors if it is not, per
+/// `config.line_ending`.
+fn format_line_ending(shebang: &mut Shebang, config: &Configuration) -> Result<()> {
+    if !matches!(shebang.line_ending, LineEnding::CrLf | LineEnding::Cr) {
+        return Ok(());
+    }
+    match config.line_ending {
+        LineEndingStyle::Lf => shebang.line_ending = LineEnding::Lf,
+        LineEndingStyle::Preserve => {}
+        LineEndingStyle::Error => {
+            bail!("Shebang line ends with a carriage return, which the kernel takes as part of it")
+        }
+    }
+    Ok(())
+}
+
+/// Errors if `config.multiple_arguments` says so and the shebang passes multiple arguments to
+/// the interpreter, which the kernel would pass as one.
+///
+/// `env -S` splits its argument, and `perl` parses switches from the shebang line itself, so
+/// these are fine.
+fn check_multiple_arguments(shebang: &Shebang, config: &Configuration) -> Result<()> {
+    if config.multiple_arguments == MultipleArguments::Allow {
+        return Ok(());
+    }
+    let multiple = match shebang.env_args() {
+        Some(env_args) => !env_args.split_string && env_args.len() > 1,
+        None => !shebang.interpreter_name().starts_with("perl") && shebang.args.len() > 1,
+    };
+    if multiple {
+        let args: Vec<_> = shebang.args.iter().map(|arg| arg.value.as_ref()).collect();
+        bail!(
+            "Shebang passes multiple arguments to {} as a single one: {}{}",
+            shebang.interpreter.value,
+            args.join(" "),
+            if shebang.is_env() {
+                "; use `env -S` to split them"
+            } else {
+                ""
+            }
+        );
     }
-    Ok(None)
+    Ok(())
+}
+
+/// Errors if the shebang line is longer than `config.max_length`.
+///
+/// Linux truncates shebang lines longer than 127 bytes, or 255 since 5.1.
+fn check_max_length(shebang: &Shebang, config: &Configuration) -> Result<()> {
+    let Some(max_length) = config.max_length else {
+        return Ok(());
+    };
+    let length = shebang.line_length();
+    if length > max_length as usize {
+        bail!("Shebang is {length} bytes long, exceeding maxLength of {max_length}");
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::Bashisms;
+    use crate::ByteOrderMark;
+    use crate::Configuration;
+    use crate::DeniedInterpreter;
+    use crate::EnvStyle;
+    use crate::ExpectedInterpreters;
+    use crate::ForbiddenShebang;
+    use crate::InterpreterMismatch;
+    use crate::InterpreterRewrite;
+    use crate::InterpreterVersion;
+    use crate::LineEndingStyle;
+    use crate::MultipleArguments;
+    use crate::PathGlob;
+    use crate::Pattern;
+    use crate::ShebangPluginHandler;
+    use crate::VersionSuffix;
+    use crate::body_path;
+    use crate::file_matching_info;
     use crate::format_shebang;
+    use crate::format_shebang_bytes;
+    use crate::resolve_config;
+    use anyhow::Result;
+    use dprint_core::configuration::ConfigKeyMap;
+    use dprint_core::configuration::ConfigKeyValue;
+    use dprint_core::configuration::GlobalConfiguration;
+    use dprint_core::plugins::FormatConfigId;
+    use dprint_core::plugins::FormatRange;
+    use dprint_core::plugins::NullCancellationToken;
+    use dprint_core::plugins::SyncFormatRequest;
+    use dprint_core::plugins::SyncHostFormatRequest;
+    use dprint_core::plugins::SyncPluginHandler;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    fn format(text: &str, config: &Configuration) -> Result<Option<String>> {
+        format_shebang(Path::new("script"), text, config)
+    }
+
+    fn format_request(text: &str, range: FormatRange) -> Option<String> {
+        format_request_with_host(text, range, &Configuration::default(), |_| unreachable!())
+    }
+
+    fn format_request_with_host(
+        text: &str,
+        range: FormatRange,
+        config: &Configuration,
+        format_with_host: impl FnMut(SyncHostFormatRequest) -> Result<Option<Vec<u8>>>,
+    ) -> Option<String> {
+        let result = ShebangPluginHandler
+            .format(
+                SyncFormatRequest {
+                    file_path: Path::new("script"),
+                    file_bytes: text.as_bytes().to_vec(),
+                    config_id: FormatConfigId::from_raw(0),
+                    config,
+                    range,
+                    token: &NullCancellationToken,
+                },
+                format_with_host,
+            )
+            .unwrap();
+        result.map(|bytes| String::from_utf8(bytes).unwrap())
+    }
 
     #[test]
     fn empty() {
         let text = "";
-        assert_eq!(format_shebang(text).unwrap(), None);
+        assert_eq!(format(text, &Configuration::default()).unwrap(), None);
     }
 
     #[test]
     fn foo_bar() {
         let text = "foo\nbar";
-        assert_eq!(format_shebang(text).unwrap(), None);
+        assert_eq!(format(text, &Configuration::default()).unwrap(), None);
     }
 
     #[test]
     fn basic() {
         let text = "#!/foo/bar\nquux";
-        assert_eq!(format_shebang(text).unwrap(), Some(String::from(text)));
+        assert_eq!(
+            format(text, &Configuration::default()).unwrap(),
+            Some(String::from(text))
+        );
     }
 
     #[test]
     fn basic_with_args() {
         let text = "#!/foo/bar -quux\nbaz";
-        assert_eq!(format_shebang(text).unwrap(), Some(String::from(text)));
+        assert_eq!(
+            format(text, &Configuration::default()).unwrap(),
+            Some(String::from(text))
+        );
     }
 
     #[test]
     fn pre_post_space() {
         let text = "#! \t /foo/bar \t \n quux";
         assert_eq!(
-            format_shebang(text).unwrap(),
+            format(text, &Configuration::default()).unwrap(),
             Some(String::from("#!/foo/bar\n quux")) // Note spaces and tabs after /foo/bar is trimmed
         );
     }
@@ -208,10 +926,951 @@ mod tests {
     fn pre_mid_post_space() {
         let text = "#! \t /foo/bar\t  -quux\t \nbaz";
         assert_eq!(
-            format_shebang(text).unwrap(),
+            format(text, &Configuration::default()).unwrap(),
             Some(String::from("#!/foo/bar -quux\t \nbaz")) // Note spaces and tabs after -quux are kept as part of args
         );
     }
+
+    fn rewrites(rules: &[(&str, &str)]) -> Configuration {
+        Configuration {
+            interpreter_rewrites: rules
+                .iter()
+                .map(|(pattern, replacement)| InterpreterRewrite {
+                    pattern: Pattern::new(pattern).unwrap(),
+                    replacement: String::from(*replacement),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rewrite_interpreter() {
+        let config = rewrites(&[
+            ("/usr/bin/python", "/usr/bin/env python3"),
+            ("python", "python3"),
+        ]);
+        assert_eq!(
+            format("#!/usr/bin/python -u\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env python3 -u\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/local/bin/python\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/local/bin/python3\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/perl\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/perl\nfoo"))
+        );
+    }
+
+    #[test]
+    fn rewrite_interpreter_idempotent() {
+        for (rule, text, expected) in [
+            (
+                ("re:python", "python3"),
+                "#!/usr/bin/python\n",
+                "#!/usr/bin/python3\n",
+            ),
+            (
+                ("re:bin/", "local/bin/"),
+                "#!/usr/bin/ruby\n",
+                "#!/usr/local/bin/ruby\n",
+            ),
+        ] {
+            let config = rewrites(&[rule]);
+            let formatted = format(text, &config).unwrap().unwrap();
+            assert_eq!(formatted, expected);
+            assert_eq!(
+                format(&formatted, &config).unwrap().as_deref(),
+                Some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn prefer_env() {
+        let config = Configuration {
+            env_style: EnvStyle::PreferEnv,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("#!/bin/bash\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env bash\nfoo"))
+        );
+        assert_eq!(
+            format("#!/bin/bash -e\nfoo", &config).unwrap(),
+            Some(String::from("#!/bin/bash -e\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env bash\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env bash\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/local/bin/node\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env node\nfoo"))
+        );
+        assert_eq!(
+            format("#!/opt/venv/bin/python\nfoo", &config).unwrap(),
+            Some(String::from("#!/opt/venv/bin/python\nfoo"))
+        );
+        assert_eq!(
+            format("#!python\nfoo", &config).unwrap(),
+            Some(String::from("#!python\nfoo"))
+        );
+
+        let config = Configuration {
+            absolute_paths: BTreeMap::from([(
+                String::from("python"),
+                String::from("/opt/venv/bin/python"),
+            )]),
+            ..config
+        };
+        assert_eq!(
+            format("#!/opt/venv/bin/python\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env python\nfoo"))
+        );
+    }
+
+    #[test]
+    fn prefer_absolute() {
+        let config = Configuration {
+            env_style: EnvStyle::PreferAbsolute,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("#!/usr/bin/env bash\nfoo", &config).unwrap(),
+            Some(String::from("#!/bin/bash\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env  sh  -e\nfoo", &config).unwrap(),
+            Some(String::from("#!/bin/sh -e\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env python3\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env python3\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env -i bash\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env -i bash\nfoo"))
+        );
+    }
+
+    #[test]
+    fn env_split_string() {
+        let config = Configuration::default();
+        assert_eq!(
+            format("#!/usr/bin/env  -S  python3 \t-u  -X dev \nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env -S python3 -u -X dev\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env -S bash -c 'echo  hi'\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env -S bash -c 'echo  hi'\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env python3  -u\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env python3  -u\nfoo"))
+        );
+
+        let config = Configuration {
+            env_split_string: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("#!/usr/bin/env python3  -u\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env -S python3 -u\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env python3\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env python3\nfoo"))
+        );
+    }
+
+    #[test]
+    fn prefer_env_split_string() {
+        let config = Configuration {
+            env_style: EnvStyle::PreferEnv,
+            env_split_string: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("#!/usr/bin/perl -w\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env -S perl -w\nfoo"))
+        );
+    }
+
+    #[test]
+    fn prefer_absolute_split_string() {
+        let config = Configuration {
+            env_style: EnvStyle::PreferAbsolute,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("#!/usr/bin/env -S bash -e\nfoo", &config).unwrap(),
+            Some(String::from("#!/bin/bash -e\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env -S bash -e -u\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env -S bash -e -u\nfoo"))
+        );
+        assert_eq!(
+            format("#!/usr/bin/env -S FOO=bar bash\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env -S FOO=bar bash\nfoo"))
+        );
+    }
+
+    #[test]
+    fn multiple_arguments() {
+        let config = Configuration {
+            multiple_arguments: MultipleArguments::Error,
+            ..Default::default()
+        };
+        for text in [
+            "#!/usr/bin/env python3\nfoo",
+            "#!/usr/bin/env -S python3 -u\nfoo",
+            "#!/bin/bash -e\nfoo",
+            "#!/usr/bin/perl -w -T\nfoo",
+        ] {
+            assert!(format(text, &config).is_ok(), "{text}");
+        }
+        let err = format("#!/usr/bin/env python3 -u\nfoo", &config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Shebang passes multiple arguments to /usr/bin/env as a single one: python3 -u; \
+             use `env -S` to split them"
+        );
+        assert!(format("#!/bin/bash -e -u\nfoo", &config).is_err());
+
+        let config = Configuration {
+            env_split_string: true,
+            ..config
+        };
+        assert!(format("#!/usr/bin/env python3 -u\nfoo", &config).is_ok());
+    }
+
+    #[test]
+    fn max_length() {
+        let config = Configuration {
+            max_length: Some(18),
+            ..Default::default()
+        };
+        assert!(format("#!/usr/bin/python3\nfoo", &config).is_ok());
+        assert!(format("#!  /usr/bin/python3 \nfoo", &config).is_ok());
+        let err = format("#!/usr/bin/python3 -u\nfoo", &config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Shebang is 21 bytes long, exceeding maxLength of 18"
+        );
+    }
+
+    #[test]
+    fn trim_and_collapse_whitespace() {
+        let text = "#!/foo/bar  -quux \t baz\t \nqux";
+        let config = Configuration {
+            trim_trailing_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format(text, &config).unwrap(),
+            Some(String::from("#!/foo/bar -quux \t baz\nqux"))
+        );
+        let config = Configuration {
+            collapse_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            format(text, &config).unwrap(),
+            Some(String::from("#!/foo/bar -quux baz\t \nqux"))
+        );
+        let text = "#!/usr/bin/env  -S bash -c 'echo  hi'\n";
+        assert_eq!(
+            format(text, &config).unwrap(),
+            Some(String::from("#!/usr/bin/env -S bash -c 'echo  hi'\n"))
+        );
+    }
+
+    #[test]
+    fn expected_interpreters() {
+        let mut config = Configuration {
+            expected_interpreters: vec![ExpectedInterpreters {
+                files: String::from(".sh"),
+                interpreters: vec![Pattern::new("sh").unwrap(), Pattern::new("bash").unwrap()],
+            }],
+            ..Default::default()
+        };
+        let path = Path::new("foo/bar.sh");
+        for text in ["#!/bin/sh\nfoo", "#!/usr/bin/env bash\nfoo"] {
+            assert!(format_shebang(path, text, &config).is_ok(), "{text}");
+        }
+        assert!(format_shebang(Path::new("bar.pl"), "#!/usr/bin/perl\nfoo", &config).is_ok());
+        let err = format_shebang(path, "#!/usr/bin/perl -w\nfoo", &config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Shebang runs /usr/bin/perl, expected sh or bash for bar.sh"
+        );
+
+        config.interpreter_mismatch = InterpreterMismatch::Fix;
+        assert_eq!(
+            format_shebang(path, "#!/usr/bin/env perl\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/env sh\nfoo"))
+        );
+        let err = format_shebang(path, "#!/usr/bin/perl -w\nfoo", &config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Shebang runs /usr/bin/perl with arguments that may not apply to sh; \
+             replace it manually"
+        );
+
+        config.expected_interpreters[0].interpreters = vec![Pattern::new("python3").unwrap()];
+        assert_eq!(
+            format_shebang(path, "#!/usr/bin/python2 -u\nfoo", &config).unwrap(),
+            Some(String::from("#!/usr/bin/python3 -u\nfoo"))
+        );
+    }
+
+    #[test]
+    fn file_matching() {
+        let config = Configuration {
+            extensions: vec![String::from("tcl"), String::from("sh")],
+            exclude_extensions: vec![String::from("t"), String::from("java")],
+            file_names: vec![String::from("Justfile")],
+            ..Default::default()
+        };
+        let info = file_matching_info(&config);
+        assert!(info.file_extensions.contains(&String::from("tcl")));
+        assert!(!info.file_extensions.contains(&String::from("t")));
+        assert!(!info.file_extensions.contains(&String::from("java")));
+        assert_eq!(
+            info.file_extensions.iter().filter(|e| *e == "sh").count(),
+            1
+        );
+        assert_eq!(info.file_names, ["Makefile", "GNUmakefile", "Justfile"]);
+
+        let config = Configuration {
+            default_file_matching: false,
+            extensions: vec![String::from("nu")],
+            ..Default::default()
+        };
+        let info = file_matching_info(&config);
+        assert_eq!(info.file_extensions, ["nu"]);
+        assert!(info.file_names.is_empty());
+    }
+
+    #[test]
+    fn is_script() {
+        let config = Configuration {
+            require_shebang: vec![PathGlob::new("bin/*").unwrap()],
+            ..Default::default()
+        };
+        let is_script =
+            |path, bytes| ShebangPluginHandler::is_script(Path::new(path), bytes, &config);
+        assert!(is_script("/project/foo", b"#!/bin/sh\n"));
+        assert!(is_script("/project/bin/foo", b"echo foo\n"));
+        assert!(!is_script("/project/foo", b"\x7fELF"));
+        assert!(
+            format_shebang_bytes(Path::new("/project/bin/foo"), b"echo foo\n", &config).is_err()
+        );
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let text = "\u{feff}#! /bin/sh\nfoo";
+        let mut config = Configuration::default();
+        assert_eq!(format(text, &config).unwrap(), None);
+        config.byte_order_mark = ByteOrderMark::Remove;
+        assert_eq!(
+            format(text, &config).unwrap(),
+            Some(String::from("#!/bin/sh\nfoo"))
+        );
+        assert_eq!(format("\u{feff}foo", &config).unwrap(), None);
+        config.byte_order_mark = ByteOrderMark::Error;
+        assert!(format(text, &config).is_err());
+        assert!(ShebangPluginHandler::is_script(
+            Path::new("foo"),
+            text.as_bytes(),
+            &config
+        ));
+    }
+
+    #[test]
+    fn non_utf8() {
+        let config = Configuration::default();
+        let path = Path::new("script");
+        assert_eq!(
+            format_shebang_bytes(path, b"#! /usr/bin/perl\r\n# caf\xe9\n", &config).unwrap(),
+            Some(b"#!/usr/bin/perl\r\n# caf\xe9\n".to_vec())
+        );
+        assert_eq!(
+            format_shebang_bytes(path, b"#! /bin/sh\nexit 0\n\x1f\x8b\x08\x00", &config).unwrap(),
+            Some(b"#!/bin/sh\nexit 0\n\x1f\x8b\x08\x00".to_vec())
+        );
+        assert_eq!(
+            format_shebang_bytes(path, b"\xe9\n", &config).unwrap(),
+            None
+        );
+        assert!(format_shebang_bytes(path, b"#!/usr/bin/caf\xe9\n", &config).is_err());
+    }
+
+    #[test]
+    fn line_ending() {
+        let mut config = Configuration::default();
+        assert_eq!(
+            format("#!/bin/sh \r\nfoo\r\n", &config).unwrap(),
+            Some(String::from("#!/bin/sh\r\nfoo\r\n"))
+        );
+        config.line_ending = LineEndingStyle::Lf;
+        assert_eq!(
+            format("#!/bin/sh \r\nfoo\r\n", &config).unwrap(),
+            Some(String::from("#!/bin/sh\nfoo\r\n"))
+        );
+        assert_eq!(
+            format("#!/bin/sh\rfoo", &config).unwrap(),
+            Some(String::from("#!/bin/sh\nfoo"))
+        );
+        config.line_ending = LineEndingStyle::Error;
+        assert!(format("#!/bin/sh\r\nfoo", &config).is_err());
+        assert!(format("#!/bin/sh\nfoo\r\n", &config).is_ok());
+    }
+
+    #[test]
+    fn format_file() {
+        assert_eq!(
+            format_request("#! /bin/sh\nfoo\n", None),
+            Some(String::from("#!/bin/sh\nfoo\n"))
+        );
+        assert_eq!(format_request("#!/bin/sh\nfoo\n", None), None);
+        assert_eq!(format_request("foo\n", None), None);
+    }
+
+    #[test]
+    fn format_range() {
+        let text = "#! /bin/sh\nfoo\nbar\n";
+        for range in [0..0, 0..3, 3..5, 5..12, 0..text.len()] {
+            assert_eq!(
+                format_request(text, Some(range.clone())),
+                Some(String::from("#!/bin/sh\nfoo\nbar\n")),
+                "{range:?}"
+            );
+        }
+        for range in [11..11, 11..14, 15..text.len()] {
+            assert_eq!(format_request(text, Some(range.clone())), None, "{range:?}");
+        }
+    }
+
+    #[test]
+    fn format_body() {
+        let config = Configuration {
+            format_body: true,
+            ..Default::default()
+        };
+        let mut requests = Vec::new();
+        let mut host = |request: SyncHostFormatRequest| {
+            requests.push((
+                request.file_path.to_path_buf(),
+                String::from_utf8(request.file_bytes.to_vec()).unwrap(),
+                request.range,
+            ));
+            Ok(Some(request.file_bytes.to_ascii_uppercase()))
+        };
+        assert_eq!(
+            format_request_with_host("#! /usr/bin/env node\nfoo()\n", None, &config, &mut host),
+            Some(String::from("#!/usr/bin/env node\nFOO()\n"))
+        );
+        assert_eq!(
+            format_request_with_host("#!/bin/sh\nfoo\nbar\n", Some(12..15), &config, &mut host),
+            Some(String::from("#!/bin/sh\nFOO\nBAR\n"))
+        );
+        assert_eq!(
+            format_request_with_host("#!/usr/bin/perl\nfoo\n", None, &config, &mut host),
+            None
+        );
+        assert_eq!(
+            format_request_with_host("#!/bin/sh", None, &config, &mut host),
+            None
+        );
+        assert_eq!(
+            requests,
+            [
+                ("script.js".into(), String::from("foo()\n"), None),
+                ("script.sh".into(), String::from("foo\nbar\n"), Some(2..5)),
+            ]
+        );
+
+        assert_eq!(
+            format_request_with_host("#! /bin/sh\nfoo\n", None, &config, |_| Ok(None)),
+            Some(String::from("#!/bin/sh\nfoo\n"))
+        );
+    }
+
+    #[test]
+    fn format_body_inserted_shebang() {
+        let config = Configuration {
+            format_body: true,
+            require_shebang: vec![PathGlob::new("bin/*").unwrap()],
+            default_shebangs: BTreeMap::from([(String::from("sh"), String::from("#!/bin/sh"))]),
+            ..Default::default()
+        };
+        let mut ranges = Vec::new();
+        let result = ShebangPluginHandler
+            .format(
+                SyncFormatRequest {
+                    file_path: Path::new("bin/foo.sh"),
+                    file_bytes: b"foo\nbar\n".to_vec(),
+                    config_id: FormatConfigId::from_raw(0),
+                    config: &config,
+                    range: Some(2..7),
+                    token: &NullCancellationToken,
+                },
+                |request| {
+                    ranges.push(request.range);
+                    Ok(Some(request.file_bytes.to_ascii_uppercase()))
+                },
+            )
+            .unwrap();
+        assert_eq!(result.as_deref(), Some(&b"#!/bin/sh\nFOO\nBAR\n"[..]));
+        assert_eq!(ranges, [Some(2..7)]);
+
+        let config = Configuration {
+            byte_order_mark: ByteOrderMark::Remove,
+            ..config
+        };
+        let result = ShebangPluginHandler
+            .format(
+                SyncFormatRequest {
+                    file_path: Path::new("bin/foo.sh"),
+                    file_bytes: b"\xef\xbb\xbffoo\nbar\n".to_vec(),
+                    config_id: FormatConfigId::from_raw(0),
+                    config: &config,
+                    range: Some(5..10),
+                    token: &NullCancellationToken,
+                },
+                |request| {
+                    ranges.push(request.range);
+                    Ok(Some(request.file_bytes.to_ascii_uppercase()))
+                },
+            )
+            .unwrap();
+        assert_eq!(result.as_deref(), Some(&b"#!/bin/sh\nFOO\nBAR\n"[..]));
+        assert_eq!(ranges, [Some(2..7), Some(2..7)]);
+    }
+
+    #[test]
+    fn format_body_reentry() {
+        let config_map = ConfigKeyMap::from([
+            (String::from("formatBody"), ConfigKeyValue::Bool(true)),
+            (String::from("insertShebangs"), ConfigKeyValue::Bool(true)),
+            (
+                String::from("requireShebang"),
+                ConfigKeyValue::Array(vec![ConfigKeyValue::String(String::from("bin/*"))]),
+            ),
+            (
+                String::from("defaultShebangs"),
+                ConfigKeyValue::Object(ConfigKeyMap::from([(
+                    String::from("sh"),
+                    ConfigKeyValue::String(String::from("#!/bin/sh")),
+                )])),
+            ),
+        ]);
+        fn format(
+            file_path: &Path,
+            text: &[u8],
+            config_map: ConfigKeyMap,
+            format_with_host: impl FnMut(SyncHostFormatRequest) -> Result<Option<Vec<u8>>>,
+        ) -> Result<Option<Vec<u8>>> {
+            let config = resolve_config(config_map, &GlobalConfiguration::default()).config;
+            ShebangPluginHandler.format(
+                SyncFormatRequest {
+                    file_path,
+                    file_bytes: text.to_vec(),
+                    config_id: FormatConfigId::from_raw(0),
+                    config: &config,
+                    range: None,
+                    token: &NullCancellationToken,
+                },
+                format_with_host,
+            )
+        }
+        // The host routes the body back to this plugin, as bin/foo.sh matches its extensions.
+        let host = |request: SyncHostFormatRequest| {
+            let mut config_map = config_map.clone();
+            config_map.extend(request.override_config.clone());
+            format(
+                request.file_path,
+                request.file_bytes,
+                config_map,
+                |_| unreachable!(),
+            )
+        };
+        assert_eq!(
+            format(
+                Path::new("bin/foo"),
+                b"#! /bin/sh\necho hi\n",
+                config_map.clone(),
+                host
+            )
+            .unwrap()
+            .as_deref(),
+            Some(&b"#!/bin/sh\necho hi\n"[..])
+        );
+    }
+
+    #[test]
+    fn body_file_path() {
+        assert_eq!(
+            body_path(Path::new("bin/foo"), "py"),
+            Path::new("bin/foo.py")
+        );
+        assert_eq!(body_path(Path::new("foo.py"), "py"), Path::new("foo.py"));
+        assert_eq!(
+            body_path(Path::new("foo.cgi"), "py"),
+            Path::new("foo.cgi.py")
+        );
+    }
+
+    #[test]
+    fn interpreter_versions() {
+        let config = Configuration {
+            interpreter_versions: vec![
+                InterpreterVersion {
+                    name: String::from("python"),
+                    default: Some("3".parse().unwrap()),
+                    minimum: Some("3.11".parse().unwrap()),
+                    suffix: VersionSuffix::Major,
+                },
+                InterpreterVersion {
+                    name: String::from("node"),
+                    default: None,
+                    minimum: None,
+                    suffix: VersionSuffix::None,
+                },
+                InterpreterVersion {
+                    name: String::from("ruby"),
+                    default: Some("3.2".parse().unwrap()),
+                    minimum: None,
+                    suffix: VersionSuffix::Preserve,
+                },
+            ],
+            ..Default::default()
+        };
+        for (text, expected) in [
+            ("#!/usr/bin/python\n", "#!/usr/bin/python3\n"),
+            (
+                "#!/usr/bin/env python3.12 -u\n",
+                "#!/usr/bin/env python3 -u\n",
+            ),
+            ("#!/usr/bin/env node18\n", "#!/usr/bin/env node\n"),
+            ("#!/usr/bin/ruby\n", "#!/usr/bin/ruby3.2\n"),
+            ("#!/usr/bin/ruby3.1\n", "#!/usr/bin/ruby3.1\n"),
+            ("#!/usr/bin/perl5\n", "#!/usr/bin/perl5\n"),
+        ] {
+            assert_eq!(
+                format(text, &config).unwrap().as_deref(),
+                Some(expected),
+                "{text}"
+            );
+        }
+        for (text, message) in [
+            (
+                "#!/usr/bin/python3.8\n",
+                "Shebang runs python3.8, older than the minimum version 3.11",
+            ),
+            (
+                "#!/usr/bin/python2\n",
+                "Shebang runs python2, older than the minimum version 3.11",
+            ),
+        ] {
+            assert_eq!(format(text, &config).unwrap_err().to_string(), message);
+        }
+    }
+
+    #[test]
+    fn denied_interpreters() {
+        let mut config = Configuration {
+            denied_interpreters: vec![
+                DeniedInterpreter {
+                    pattern: Pattern::new("python2*").unwrap(),
+                    replacement: Some(String::from("python3")),
+                },
+                DeniedInterpreter {
+                    pattern: Pattern::new(r"re:^/usr/bin/perl(5[.\d]*)$").unwrap(),
+                    replacement: None,
+                },
+                DeniedInterpreter {
+                    pattern: Pattern::new("node").unwrap(),
+                    replacement: Some(String::from("deno")),
+                },
+            ],
+            ..Default::default()
+        };
+        for (text, message) in [
+            (
+                "#!/usr/bin/env python2.7\n",
+                "Shebang runs python2.7, which is not allowed; use python3 instead",
+            ),
+            (
+                "#!/usr/bin/python2\n",
+                "Shebang runs /usr/bin/python2, which is not allowed; use /usr/bin/python3 instead",
+            ),
+            (
+                "#!/usr/bin/perl5 -w\n",
+                "Shebang runs /usr/bin/perl5, which is not allowed",
+            ),
+        ] {
+            assert_eq!(format(text, &config).unwrap_err().to_string(), message);
+        }
+        assert_eq!(
+            format("#!/usr/bin/perl -w\n", &config).unwrap().as_deref(),
+            Some("#!/usr/bin/perl -w\n")
+        );
+
+        config.fix_denied_interpreters = true;
+        for (text, expected) in [
+            (
+                "#!/usr/bin/env python2.7 -u\n",
+                "#!/usr/bin/env python3 -u\n",
+            ),
+            ("#!/usr/bin/python2\n", "#!/usr/bin/python3\n"),
+            ("#!/usr/bin/env node\n", "#!/usr/bin/env deno\n"),
+        ] {
+            assert_eq!(
+                format(text, &config).unwrap().as_deref(),
+                Some(expected),
+                "{text}"
+            );
+        }
+        assert!(format("#!/usr/bin/perl5\n", &config).is_err());
+        assert_eq!(
+            format("#!/usr/bin/env -S node --no-warnings\n", &config)
+                .unwrap_err()
+                .to_string(),
+            "Shebang runs node with arguments that may not apply to deno; replace it manually"
+        );
+
+        config.denied_interpreters[0].replacement = Some(String::from("/usr/bin/python3"));
+        for (text, expected) in [
+            ("#!/usr/bin/env python2\n", "#!/usr/bin/python3\n"),
+            (
+                "#!/usr/bin/env -S FOO=1 python2 -u -X dev\n",
+                "#!/usr/bin/env -S FOO=1 /usr/bin/python3 -u -X dev\n",
+            ),
+        ] {
+            assert_eq!(
+                format(text, &config).unwrap().as_deref(),
+                Some(expected),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn bashisms() {
+        let mut config = Configuration {
+            bashisms: Bashisms::Error,
+            ..Default::default()
+        };
+        let text = "#!/bin/sh\nset -e\n[[ -f foo ]] && echo foo\n";
+        assert_eq!(
+            format(text, &config).unwrap_err().to_string(),
+            "Shebang runs sh, but line 3 uses `[[`, which needs bash"
+        );
+        for text in [
+            "#!/bin/sh\n[ -f foo ] && echo foo\n",
+            "#!/bin/bash\n[[ -f foo ]] && echo foo\n",
+        ] {
+            assert_eq!(format(text, &config).unwrap().as_deref(), Some(text));
+        }
+
+        config.bashisms = Bashisms::Upgrade;
+        for (text, expected) in [
+            ("#!/bin/sh -e\nsource foo\n", "#!/bin/bash -e\nsource foo\n"),
+            (
+                "#!/usr/bin/env sh\nx=(a b)\n",
+                "#!/usr/bin/env bash\nx=(a b)\n",
+            ),
+        ] {
+            assert_eq!(format(text, &config).unwrap().as_deref(), Some(expected));
+        }
+        assert_eq!(
+            format_shebang_bytes(Path::new("script"), b"#!/bin/sh\nsource \xff\n", &config)
+                .unwrap(),
+            Some(b"#!/bin/bash\nsource \xff\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn require_shebang() {
+        let mut config = Configuration {
+            require_shebang: vec![PathGlob::new("bin/*").unwrap()],
+            default_shebangs: BTreeMap::from([(String::from("sh"), String::from("#! /bin/sh"))]),
+            ..Default::default()
+        };
+        let format = |path: &str, text: &str| format_shebang(Path::new(path), text, &config);
+        assert_eq!(
+            format("bin/foo.sh", "set -e\n").unwrap().as_deref(),
+            Some("#!/bin/sh\nset -e\n")
+        );
+        assert_eq!(
+            format("bin/foo.sh", "#!/bin/bash\n").unwrap().as_deref(),
+            Some("#!/bin/bash\n")
+        );
+        assert_eq!(format("lib/foo.sh", "set -e\n").unwrap(), None);
+        assert_eq!(
+            format("bin/foo.py", "import sys\n")
+                .unwrap_err()
+                .to_string(),
+            "File has no shebang, but one is required by requireShebang"
+        );
+        assert_eq!(
+            format_shebang_bytes(Path::new("bin/foo.sh"), b"set -e\n\xff\n", &config).unwrap(),
+            Some(b"#!/bin/sh\nset -e\n\xff\n".to_vec())
+        );
+        for text in [
+            &b"\xff\n"[..],
+            b"\xef\xbb\xbfecho hi\n",
+            b"\xef\xbb\xbfabc\xff\n",
+            b"\x7fELF\x02\x01\x01\x00\n",
+        ] {
+            for path in ["bin/foo.sh", "bin/tool"] {
+                assert_eq!(
+                    format_shebang_bytes(Path::new(path), text, &config).unwrap(),
+                    None,
+                    "{path}"
+                );
+            }
+        }
+        config.byte_order_mark = ByteOrderMark::Remove;
+        assert_eq!(
+            format_shebang(Path::new("bin/foo.sh"), "\u{feff}echo hi\n", &config)
+                .unwrap()
+                .as_deref(),
+            Some("#!/bin/sh\necho hi\n")
+        );
+        config.byte_order_mark = ByteOrderMark::Error;
+        assert_eq!(
+            format_shebang(Path::new("bin/foo.sh"), "\u{feff}echo hi\n", &config)
+                .unwrap_err()
+                .to_string(),
+            "Byte order mark at the start of the file prevents inserting a shebang"
+        );
+        assert!(ShebangPluginHandler::is_script(
+            Path::new("bin/foo.py"),
+            b"",
+            &config
+        ));
+    }
+
+    #[test]
+    fn insert_shebangs() {
+        let mut config = Configuration {
+            default_shebangs: BTreeMap::from([
+                (String::from("sh"), String::from("#!/bin/sh")),
+                (String::from("py"), String::from("#!/usr/bin/env python3")),
+            ]),
+            insert_shebangs: true,
+            insert_shebangs_exclude: vec![PathGlob::new("src/**/*.py").unwrap()],
+            ..Default::default()
+        };
+        for (path, text, expected) in [
+            ("foo.sh", "set -e\n", Some("#!/bin/sh\nset -e\n")),
+            ("tools/foo.py", "", Some("#!/usr/bin/env python3\n")),
+            ("src/pkg/foo.py", "import sys\n", None),
+            ("foo.pl", "use strict;\n", None),
+        ] {
+            assert_eq!(
+                format_shebang(Path::new(path), text, &config)
+                    .unwrap()
+                    .as_deref(),
+                expected,
+                "{path}"
+            );
+            assert_eq!(
+                ShebangPluginHandler::is_script(Path::new(path), text.as_bytes(), &config),
+                expected.is_some(),
+                "{path}"
+            );
+        }
+
+        config.insert_shebangs = false;
+        assert_eq!(
+            format_shebang(Path::new("foo.sh"), "set -e\n", &config).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn forbid_shebang() {
+        let mut config = Configuration {
+            forbid_shebang: vec![
+                PathGlob::new("src/**/*.py").unwrap(),
+                PathGlob::new("*.mk").unwrap(),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            format_shebang(Path::new("src/pkg/foo.py"), "#!/usr/bin/python3\n", &config)
+                .unwrap_err()
+                .to_string(),
+            "File has a shebang, but is matched by forbidShebang"
+        );
+        assert_eq!(
+            format_shebang(Path::new("bin/foo.py"), "#! /usr/bin/python3\n", &config)
+                .unwrap()
+                .as_deref(),
+            Some("#!/usr/bin/python3\n")
+        );
+
+        config.forbidden_shebang = ForbiddenShebang::Remove;
+        assert_eq!(
+            format_shebang(Path::new("rules.mk"), "#!/usr/bin/make -f\nall:\n", &config)
+                .unwrap()
+                .as_deref(),
+            Some("all:\n")
+        );
+        assert_eq!(
+            format_shebang_bytes(
+                Path::new("rules.mk"),
+                b"#!/usr/bin/make -f\r\n\xff",
+                &config
+            )
+            .unwrap(),
+            Some(b"\xff".to_vec())
+        );
+        assert_eq!(
+            format_shebang(Path::new("rules.mk"), "all:\n", &config).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn forbid_and_insert_shebangs() {
+        let mut config = Configuration {
+            default_shebangs: BTreeMap::from([(
+                String::from("py"),
+                String::from("#!/usr/bin/env python3"),
+            )]),
+            insert_shebangs: true,
+            require_shebang: vec![PathGlob::new("**/*.py").unwrap()],
+            forbid_shebang: vec![PathGlob::new("src/**/*.py").unwrap()],
+            ..Default::default()
+        };
+        for forbidden_shebang in [ForbiddenShebang::Error, ForbiddenShebang::Remove] {
+            config.forbidden_shebang = forbidden_shebang;
+            let path = Path::new("/r/src/pkg/m.py");
+            assert_eq!(
+                format_shebang_bytes(path, b"import sys\n", &config).unwrap(),
+                None
+            );
+            assert!(!ShebangPluginHandler::is_script(
+                path,
+                b"import sys\n",
+                &config
+            ));
+            assert_eq!(
+                format_shebang_bytes(Path::new("/r/bin/m.py"), b"import sys\n", &config).unwrap(),
+                Some(b"#!/usr/bin/env python3\nimport sys\n".to_vec())
+            );
+        }
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
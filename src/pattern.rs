@@ -0,0 +1,329 @@
+use lazy_regex::Regex;
+use lazy_regex::regex::NoExpand;
+use serde::Serialize;
+use serde::Serializer;
+use std::path::Path;
+
+/// Prefix marking a pattern as a regular expression.
+const REGEX_PREFIX: &str = "re:";
+
+/// An interpreter pattern from the configuration.
+///
+/// Patterns prefixed with `re:` are regular expressions matched against the whole interpreter
+/// path. Anything else is a glob (`*`, `?`, `[...]`) or an exact string; these match the whole
+/// interpreter path if they contain a slash, and the interpreter's file name otherwise.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+    kind: PatternKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PatternKind {
+    Regex,
+    Path,
+    FileName,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self, lazy_regex::regex::Error> {
+        let (regex, kind) = if let Some(regex) = source.strip_prefix(REGEX_PREFIX) {
+            (Regex::new(regex)?, PatternKind::Regex)
+        } else {
+            let kind = if source.contains('/') {
+                PatternKind::Path
+            } else {
+                PatternKind::FileName
+            };
+            (Regex::new(&format!("^{}$", glob_to_regex(source)))?, kind)
+        };
+        Ok(Self {
+            source: source.to_string(),
+            regex,
+            kind,
+        })
+    }
+
+    /// The pattern as written in the configuration.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// The pattern as a plain string, if it is neither a regular expression nor a glob.
+    pub fn literal(&self) -> Option<&str> {
+        (self.kind != PatternKind::Regex && !self.source.contains(['*', '?', '[']))
+            .then_some(self.source.as_str())
+    }
+
+    /// Whether the pattern matches the interpreter.
+    pub fn is_match(&self, interpreter: &str) -> bool {
+        match self.kind {
+            PatternKind::Regex | PatternKind::Path => self.regex.is_match(interpreter),
+            PatternKind::FileName => self.regex.is_match(file_name(interpreter)),
+        }
+    }
+
+    /// Applies `replacement` to the interpreter if the pattern matches it.
+    ///
+    /// Regular expressions replace the matched part, with `$1`, `$name` etc. expanded, unless
+    /// the interpreter already has the expanded replacement around it, so that e.g. `re:python`
+    /// replaced with `python3` does not turn `python3` into `python33`. Globs and exact strings
+    /// replace the whole interpreter when the replacement contains a slash, or just the matched
+    /// file name otherwise.
+    pub fn replace(&self, interpreter: &str, replacement: &str) -> Option<String> {
+        if !self.is_match(interpreter) {
+            return None;
+        }
+        Some(match self.kind {
+            PatternKind::Regex => {
+                let captures = self.regex.captures(interpreter)?;
+                let matched = captures.get(0)?;
+                let mut expanded = String::new();
+                captures.expand(replacement, &mut expanded);
+                let replaced = expanded.match_indices(matched.as_str()).any(|(i, _)| {
+                    matched
+                        .start()
+                        .checked_sub(i)
+                        .and_then(|start| interpreter.get(start..))
+                        .is_some_and(|rest| rest.starts_with(&expanded))
+                });
+                if replaced {
+                    return Some(interpreter.to_string());
+                }
+                format!(
+                    "{}{expanded}{}",
+                    &interpreter[..matched.start()],
+                    &interpreter[matched.end()..]
+                )
+            }
+            _ if replacement.contains('/') => replacement.to_string(),
+            PatternKind::Path => self
+                .regex
+                .replace(interpreter, NoExpand(replacement))
+                .into_owned(),
+            PatternKind::FileName => {
+                let dir = &interpreter[..interpreter.len() - file_name(interpreter).len()];
+                format!("{dir}{replacement}")
+            }
+        })
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+/// A file path glob from the configuration.
+///
+/// Globs starting with a slash match from the root; others match any trailing part of a path
+/// starting at a directory boundary, so `bin/*` matches `/src/project/bin/foo`.
+#[derive(Clone, Debug)]
+pub struct PathGlob {
+    source: String,
+    regex: Regex,
+}
+
+impl PathGlob {
+    pub fn new(source: &str) -> Result<Self, lazy_regex::regex::Error> {
+        let anchor = if source.starts_with('/') {
+            "^"
+        } else {
+            "(?:^|/)"
+        };
+        let regex = Regex::new(&format!("{anchor}{}$", glob_to_regex(source)))?;
+        Ok(Self {
+            source: source.to_string(),
+            regex,
+        })
+    }
+
+    /// The glob as written in the configuration.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the glob matches `path`.
+    pub fn is_match(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy();
+        if std::path::MAIN_SEPARATOR == '/' {
+            self.regex.is_match(&path)
+        } else {
+            self.regex
+                .is_match(&path.replace(std::path::MAIN_SEPARATOR, "/"))
+        }
+    }
+}
+
+impl PartialEq for PathGlob {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for PathGlob {}
+
+impl Serialize for PathGlob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+/// Returns the part of `path` after the last slash.
+pub fn file_name(path: &str) -> &str {
+    path.rsplit_once('/').map_or(path, |(_, name)| name)
+}
+
+/// Translates a glob to an unanchored regular expression.
+///
+/// `*` and `?` do not match slashes; `**` does.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::with_capacity(glob.len() * 2);
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let mut class = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == ']' && !class.is_empty() && class != "!" {
+                        closed = true;
+                        break;
+                    }
+                    class.push(c);
+                }
+                if closed {
+                    regex.push('[');
+                    if let Some(negated) = class.strip_prefix('!') {
+                        regex.push('^');
+                        regex.push_str(&negated.replace('\\', "\\\\"));
+                    } else {
+                        regex.push_str(&class.replace('\\', "\\\\"));
+                    }
+                    regex.push(']');
+                } else {
+                    regex.push_str(&lazy_regex::regex::escape(&format!("[{class}")));
+                }
+            }
+            c => regex.push_str(&lazy_regex::regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact() {
+        let pattern = Pattern::new("python").unwrap();
+        assert!(pattern.is_match("/usr/bin/python"));
+        assert!(pattern.is_match("python"));
+        assert!(!pattern.is_match("/usr/bin/python3"));
+        assert_eq!(
+            pattern.replace("/usr/bin/python", "python3"),
+            Some(String::from("/usr/bin/python3"))
+        );
+        assert_eq!(
+            pattern.replace("/usr/bin/python", "/usr/bin/env python3"),
+            Some(String::from("/usr/bin/env python3"))
+        );
+    }
+
+    #[test]
+    fn literal() {
+        assert_eq!(Pattern::new("/bin/sh").unwrap().literal(), Some("/bin/sh"));
+        assert_eq!(Pattern::new("python3*").unwrap().literal(), None);
+        assert_eq!(Pattern::new("re:sh").unwrap().literal(), None);
+    }
+
+    #[test]
+    fn exact_path() {
+        let pattern = Pattern::new("/usr/bin/python").unwrap();
+        assert!(pattern.is_match("/usr/bin/python"));
+        assert!(!pattern.is_match("/usr/local/bin/python"));
+    }
+
+    #[test]
+    fn glob() {
+        let pattern = Pattern::new("/usr/*/bin/python[23]*").unwrap();
+        assert!(pattern.is_match("/usr/local/bin/python3.11"));
+        assert!(!pattern.is_match("/usr/local/bin/python"));
+        assert!(!pattern.is_match("/usr/a/b/bin/python3"));
+        assert!(
+            Pattern::new("/opt/**/python")
+                .unwrap()
+                .is_match("/opt/a/b/python")
+        );
+        assert!(Pattern::new("[!p]erl").unwrap().is_match("/usr/bin/Perl"));
+    }
+
+    #[test]
+    fn regex() {
+        let pattern = Pattern::new(r"re:^/usr/local/bin/(\w+)$").unwrap();
+        assert_eq!(
+            pattern.replace("/usr/local/bin/ruby", "/usr/bin/env $1"),
+            Some(String::from("/usr/bin/env ruby"))
+        );
+        assert_eq!(pattern.replace("/usr/bin/ruby", "/usr/bin/env $1"), None);
+
+        let pattern = Pattern::new("re:python").unwrap();
+        for (interpreter, expected) in [
+            ("/usr/bin/python", "/usr/bin/python3"),
+            ("/usr/bin/python3", "/usr/bin/python3"),
+            ("/usr/bin/python3.11", "/usr/bin/python3.11"),
+        ] {
+            assert_eq!(
+                pattern.replace(interpreter, "python3").as_deref(),
+                Some(expected)
+            );
+        }
+        let pattern = Pattern::new("re:bin/").unwrap();
+        assert_eq!(
+            pattern
+                .replace("/usr/local/bin/sh", "local/bin/")
+                .as_deref(),
+            Some("/usr/local/bin/sh")
+        );
+    }
+
+    #[test]
+    fn path_glob() {
+        let glob = PathGlob::new("bin/*").unwrap();
+        assert!(glob.is_match(Path::new("/src/project/bin/foo")));
+        assert!(glob.is_match(Path::new("bin/foo")));
+        assert!(!glob.is_match(Path::new("/src/project/sbin/foo")));
+        assert!(!glob.is_match(Path::new("/src/project/bin/foo/bar")));
+        let glob = PathGlob::new("/src/**/*.py").unwrap();
+        assert!(glob.is_match(Path::new("/src/a/b/c.py")));
+        assert!(glob.is_match(Path::new("/src/c.py")));
+        assert!(!glob.is_match(Path::new("/project/src/c.py")));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Pattern::new("re:(").is_err());
+    }
+}
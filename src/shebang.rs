@@ -0,0 +1,271 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+/// A parsed shebang line.
+///
+/// Whitespace is kept as written, so rendering an unmodified `Shebang` with [`Display`] gives back
+/// the parsed line verbatim. Parts borrow from the parsed text until modified.
+///
+/// [`Display`]: fmt::Display
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shebang<'a> {
+    /// `#!` and any whitespace following it.
+    pub prefix: Cow<'a, str>,
+    /// The interpreter; its `space_before` is always empty, see `prefix`.
+    pub interpreter: Token<'a>,
+    /// Arguments following the interpreter, split on spaces and tabs.
+    pub args: Vec<Token<'a>>,
+    /// Whitespace after the last token.
+    pub trailing_space: Cow<'a, str>,
+    pub line_ending: LineEnding,
+    /// Byte span of the whole line in the parsed text, including its line ending.
+    pub span: Range<usize>,
+}
+
+/// A whitespace separated token on a shebang line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token<'a> {
+    /// Whitespace preceding the token.
+    pub space_before: Cow<'a, str>,
+    pub value: Cow<'a, str>,
+    /// Byte span of `value` in the parsed text; empty for tokens not parsed from text.
+    pub span: Range<usize>,
+}
+
+impl<'a> Token<'a> {
+    /// Creates a token preceded by a single space.
+    pub fn new(value: impl Into<Cow<'a, str>>) -> Self {
+        Self {
+            space_before: Cow::Borrowed(" "),
+            value: value.into(),
+            span: 0..0,
+        }
+    }
+}
+
+/// Line terminator of a shebang line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    /// The shebang is the last line and has no terminator.
+    None,
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::None => "",
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+impl Shebang<'_> {
+    /// The file name part of the interpreter path, e.g. `bash` for `/bin/bash`.
+    pub fn interpreter_name(&self) -> &str {
+        crate::pattern::file_name(&self.interpreter.value)
+    }
+
+    /// Whether the interpreter is `env`.
+    pub fn is_env(&self) -> bool {
+        self.interpreter_name() == "env"
+    }
+
+    /// Removes whitespace before the interpreter, separates it from the arguments with a single
+    /// space, and removes trailing whitespace if there are no arguments.
+    ///
+    /// Whitespace between and after arguments is kept, as it may be significant to the
+    /// interpreter.
+    pub fn normalize_whitespace(&mut self) {
+        self.prefix = Cow::Borrowed("#!");
+        if let Some(arg) = self.args.first_mut() {
+            arg.space_before = Cow::Borrowed(" ");
+        } else {
+            self.trailing_space = Cow::Borrowed("");
+        }
+    }
+
+    /// The pieces the rendered line consists of, in order.
+    fn parts(&self) -> impl Iterator<Item = &str> {
+        [self.prefix.as_ref(), self.interpreter.value.as_ref()]
+            .into_iter()
+            .chain(
+                self.args
+                    .iter()
+                    .flat_map(|arg| [arg.space_before.as_ref(), arg.value.as_ref()]),
+            )
+            .chain([self.trailing_space.as_ref(), self.line_ending.as_str()])
+    }
+
+    /// Length of the rendered line in bytes, excluding the line ending.
+    pub fn line_length(&self) -> usize {
+        self.parts().map(str::len).sum::<usize>() - self.line_ending.as_str().len()
+    }
+
+    /// Whether rendering gives exactly `text`, checked without allocating.
+    pub fn renders_as(&self, text: &str) -> bool {
+        let mut rest = text;
+        self.parts().all(|part| match rest.strip_prefix(part) {
+            Some(remaining) => {
+                rest = remaining;
+                true
+            }
+            None => false,
+        }) && rest.is_empty()
+    }
+
+    /// Whether any argument contains quotes or backslashes, which make whitespace between
+    /// arguments significant to `env -S`.
+    pub fn has_quoted_args(&self) -> bool {
+        self.args
+            .iter()
+            .any(|arg| arg.value.contains(['"', '\'', '\\']))
+    }
+
+    /// Separates all arguments with single spaces, unless [quoted](Self::has_quoted_args).
+    pub fn collapse_whitespace(&mut self) {
+        if self.has_quoted_args() {
+            return;
+        }
+        for arg in &mut self.args {
+            arg.space_before = Cow::Borrowed(" ");
+        }
+    }
+
+    /// Removes whitespace after the last token.
+    pub fn trim_trailing_whitespace(&mut self) {
+        self.trailing_space = Cow::Borrowed("");
+    }
+}
+
+impl fmt::Display for Shebang<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.parts().try_for_each(|part| f.write_str(part))
+    }
+}
+
+/// Parses the shebang on the first line of `text`.
+///
+/// Returns `None` if `text` does not start with `#!` followed by an interpreter.
+pub fn parse(text: &str) -> Option<Shebang<'_>> {
+    let line_start = "#!".len();
+    let rest = text.strip_prefix("#!")?;
+    let line_end = line_start + rest.find(['\r', '\n']).unwrap_or(rest.len());
+    let (line_ending, end) = match &text.as_bytes()[line_end..] {
+        [b'\r', b'\n', ..] => (LineEnding::CrLf, line_end + 2),
+        [b'\r', ..] => (LineEnding::Cr, line_end + 1),
+        [b'\n', ..] => (LineEnding::Lf, line_end + 1),
+        _ => (LineEnding::None, line_end),
+    };
+
+    let is_space = |b: &u8| *b == b' ' || *b == b'\t';
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = line_start;
+    let trailing_space = loop {
+        let space_start = pos;
+        pos += bytes[pos..line_end]
+            .iter()
+            .take_while(|b| is_space(b))
+            .count();
+        if pos == line_end {
+            break &text[space_start..line_end];
+        }
+        let value_start = pos;
+        pos += bytes[pos..line_end]
+            .iter()
+            .take_while(|b| !is_space(b))
+            .count();
+        tokens.push(Token {
+            space_before: Cow::Borrowed(&text[space_start..value_start]),
+            value: Cow::Borrowed(&text[value_start..pos]),
+            span: value_start..pos,
+        });
+    };
+
+    let mut args = tokens.into_iter();
+    let mut interpreter = args.next()?;
+    let prefix = Cow::Borrowed(&text[..interpreter.span.start]);
+    interpreter.space_before = Cow::Borrowed("");
+    Some(Shebang {
+        prefix,
+        interpreter,
+        args: args.collect(),
+        trailing_space: Cow::Borrowed(trailing_space),
+        line_ending,
+        span: 0..end,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_shebang() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("foo\n#!/bin/sh"), None);
+        assert_eq!(parse("#!"), None);
+        assert_eq!(parse("#! \t\nfoo"), None);
+    }
+
+    #[test]
+    fn tokens() {
+        let text = "#! /usr/bin/env  -S\tpython3 \r\nfoo";
+        let shebang = parse(text).unwrap();
+        assert_eq!(shebang.prefix, "#! ");
+        assert_eq!(shebang.interpreter.value, "/usr/bin/env");
+        assert_eq!(&text[shebang.interpreter.span.clone()], "/usr/bin/env");
+        let args: Vec<_> = shebang
+            .args
+            .iter()
+            .map(|arg| (arg.space_before.as_ref(), arg.value.as_ref()))
+            .collect();
+        assert_eq!(args, [("  ", "-S"), ("\t", "python3")]);
+        assert_eq!(&text[shebang.args[1].span.clone()], "python3");
+        assert_eq!(shebang.trailing_space, " ");
+        assert_eq!(shebang.line_ending, LineEnding::CrLf);
+        assert_eq!(&text[shebang.span.end..], "foo");
+        assert!(shebang.is_env());
+    }
+
+    #[test]
+    fn round_trip() {
+        for text in [
+            "#!/bin/sh",
+            "#!/bin/sh\n",
+            "#!  /bin/sh -e  \r",
+            "#!/bin/sh\t-e\r\n",
+        ] {
+            assert_eq!(parse(text).unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn renders_as() {
+        let text = "#! /bin/sh -e \n";
+        let mut shebang = parse(text).unwrap();
+        assert!(shebang.renders_as(text));
+        assert!(!shebang.renders_as("#! /bin/sh -e \nfoo"));
+        assert_eq!(shebang.line_length(), text.len() - 1);
+        shebang.normalize_whitespace();
+        assert!(!shebang.renders_as(text));
+        assert!(shebang.renders_as("#!/bin/sh -e \n"));
+    }
+
+    #[test]
+    fn normalize_whitespace() {
+        let mut shebang = parse("#! \t/bin/sh\t -e  -u \nfoo").unwrap();
+        shebang.normalize_whitespace();
+        assert_eq!(shebang.to_string(), "#!/bin/sh -e  -u \n");
+
+        let mut shebang = parse("#! /bin/sh \t").unwrap();
+        shebang.normalize_whitespace();
+        assert_eq!(shebang.to_string(), "#!/bin/sh");
+    }
+}
@@ -0,0 +1,104 @@
+use serde::Serialize;
+use serde::Serializer;
+use std::fmt;
+use std::str::FromStr;
+
+/// A version of dot separated numbers, e.g. `3.11`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version(Vec<u32>);
+
+impl Version {
+    /// The first number of the version.
+    pub fn major(&self) -> u32 {
+        self.0[0]
+    }
+
+    /// Whether the version is older than `other`, comparing only the numbers both have, so
+    /// `3` is not older than `3.11`.
+    pub fn is_older_than(&self, other: &Version) -> bool {
+        self.0
+            .iter()
+            .zip(&other.0)
+            .find(|(a, b)| a != b)
+            .is_some_and(|(a, b)| a < b)
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split('.')
+            .map(|number| number.parse().ok())
+            .collect::<Option<_>>()
+            .map(Self)
+            .ok_or_else(|| format!("Invalid version '{s}', expected numbers separated by dots"))
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, number) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{number}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Splits a program name into the name proper and its version suffix, e.g. `python3.11` into
+/// `python` and `3.11`.
+///
+/// The suffix is empty if the name does not end with a version.
+pub fn split_version(name: &str) -> (&str, &str) {
+    let base = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let suffix = &name[base.len()..];
+    if base.is_empty() || suffix.parse::<Version>().is_err() {
+        return (name, "");
+    }
+    (base, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(s: &str) -> Version {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(version("3.11").to_string(), "3.11");
+        assert_eq!(version("3").major(), 3);
+        assert!("3.".parse::<Version>().is_err());
+        assert!("".parse::<Version>().is_err());
+        assert!("3.x".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn is_older_than() {
+        assert!(version("3.8").is_older_than(&version("3.11")));
+        assert!(version("2").is_older_than(&version("3.11")));
+        assert!(!version("3").is_older_than(&version("3.11")));
+        assert!(!version("3.11.2").is_older_than(&version("3.11")));
+        assert!(!version("3.12").is_older_than(&version("3.11")));
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_version("python3.11"), ("python", "3.11"));
+        assert_eq!(split_version("python"), ("python", ""));
+        assert_eq!(split_version("perl5.36.0"), ("perl", "5.36.0"));
+        assert_eq!(split_version("foo.3"), ("foo.3", ""));
+        assert_eq!(split_version("42"), ("42", ""));
+    }
+}